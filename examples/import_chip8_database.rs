@@ -0,0 +1,153 @@
+//! Converts the community chip-8-database (<https://github.com/chip-8/chip-8-database>) to the
+//! bundled ROM settings database:
+//!
+//! ```text
+//! cargo run --example import_chip8_database -- chip-8-database/database/programs.json > src/roms.toml
+//! ```
+//!
+//! Each ROM's first platform picks the variant and quirks, its tickrate the speed and its pixel
+//! colours the palette. ROMs for platforms the interpreter doesn't emulate, such as MegaChip or
+//! programs with COSMAC VIP machine code, are left out, and so are key mappings.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::process;
+
+use chip8_rust::database::Database;
+use serde_json::Value;
+
+const HEADER: &str = "\
+# Settings for known ROMs, bundled with the interpreter.
+#
+# Each table is named after the SHA-1 hash of a ROM (`chip8-rust info ROM-FILENAME` prints it) and
+# may set `title`, `variant`, `quirks`, `cycles-per-frame`, `colors` and a `keys` table. Entries in
+# chip8-rust/roms.toml in the user's configuration directory are added to these and replace them
+# for the same ROM. See src/database.rs for the format.
+#
+# Generated from chip-8-database's programs.json by examples/import_chip8_database.rs.
+";
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: import_chip8_database PROGRAMS-JSON");
+            process::exit(2);
+        }
+    };
+    let result = fs::read_to_string(&path).map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+        .and_then(|programs: Value| convert(&programs));
+    match result {
+        Ok(text) => print!("{}", text),
+        Err(e) => {
+            eprintln!("Failed to convert \"{}\" due to: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The variant and quirk profile for one of chip-8-database's platform ids.
+fn platform(id: &str) -> Option<(&'static str, &'static str)> {
+    match id {
+        "originalChip8" => Some(("chip8", "vip")),
+        "modernChip8" => Some(("chip8", "modern")),
+        "chip48" => Some(("chip8", "chip48")),
+        "superchip1" | "superchip" => Some(("schip", "schip")),
+        "xochip" => Some(("xochip", "xochip")),
+        _ => None
+    }
+}
+
+/// The `roms.toml` for chip-8-database's `programs.json`, sorted by hash. Fails if the JSON isn't
+/// shaped like the database or the result doesn't parse as a settings database.
+fn convert(programs: &Value) -> Result<String, String> {
+    let programs = programs.as_array().ok_or("expected an array of programs")?;
+    let mut entries = BTreeMap::new();
+    for program in programs {
+        let title = program["title"].as_str().ok_or("a program has no title")?;
+        let roms = program["roms"].as_object().ok_or_else(|| format!("\"{}\" has no roms", title))?;
+        for (hash, rom) in roms {
+            if let Some(entry) = entry(title, rom) {
+                entries.insert(hash.to_ascii_lowercase(), entry);
+            }
+        }
+    }
+    let mut text = String::from(HEADER);
+    for (hash, entry) in entries {
+        write!(text, "\n[{}]\n{}", hash, entry).unwrap();
+    }
+    Database::parse(&text)?;
+    Ok(text)
+}
+
+/// The settings for one ROM, or `None` if none of its platforms can be emulated.
+fn entry(title: &str, rom: &Value) -> Option<String> {
+    let (variant, quirks) = rom["platforms"].as_array()?.first()?.as_str().and_then(platform)?;
+    // JSON string escapes are valid in TOML's basic strings.
+    let mut entry = format!("title = {}\nvariant = \"{}\"\nquirks = \"{}\"\n", Value::from(title), variant, quirks);
+    if let Some(tickrate) = rom["tickrate"].as_u64().filter(|&tickrate| tickrate > 0) {
+        writeln!(entry, "cycles-per-frame = {}", tickrate).unwrap();
+    }
+    let pixels: Vec<&str> = rom["colors"]["pixels"].as_array()
+        .map_or_else(Vec::new, |pixels| pixels.iter().filter_map(Value::as_str).collect());
+    if (2..=4).contains(&pixels.len()) {
+        let colors: Vec<String> = pixels.iter()
+            .map(|color| format!("\"{}\"", color.trim_start_matches('#').to_ascii_uppercase()))
+            .collect();
+        writeln!(entry, "colors = [{}]", colors.join(", ")).unwrap();
+    }
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_rust::chip8::Variant;
+    use chip8_rust::rom::sha1_hex;
+
+    /// `v0 := 5`, then a jump to itself.
+    const ROM: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    fn programs(rom_hash: &str) -> Value {
+        serde_json::json!([
+            {
+                "title": "Spin \"Loop\"",
+                "roms": {
+                    rom_hash.to_ascii_uppercase(): {
+                        "file": "spin.ch8",
+                        "platforms": ["superchip", "xochip"],
+                        "tickrate": 30,
+                        "colors": { "pixels": ["#000000", "#ffcc00"], "buzzer": "#990000" }
+                    },
+                    "0123456789abcdef0123456789abcdef01234567": {
+                        "file": "spin-megachip.ch8",
+                        "platforms": ["megachip8"]
+                    }
+                }
+            }
+        ])
+    }
+
+    #[test]
+    fn a_known_rom_resolves_to_its_settings() {
+        let database = Database::parse(&convert(&programs(&sha1_hex(&ROM))).unwrap()).unwrap();
+        assert_eq!(database.len(), 1);
+        let settings = database.lookup(&ROM).unwrap();
+        assert_eq!(settings.title.as_deref(), Some("Spin \"Loop\""));
+        assert_eq!(settings.variant, Some(Variant::SuperChip));
+        assert_eq!(settings.cycles_per_frame, Some(30));
+        assert_eq!(settings.palette.unwrap()[1], [0xFF, 0xCC, 0x00]);
+        assert!(settings.keys.is_none());
+    }
+
+    #[test]
+    fn starts_with_the_header() {
+        assert_eq!(convert(&serde_json::json!([])).unwrap(), HEADER);
+        assert!(convert(&serde_json::json!({})).is_err());
+        assert!(convert(&serde_json::json!([{ "title": "No ROMs" }])).is_err());
+        assert!(convert(&programs("not a hash")).is_err());
+    }
+}
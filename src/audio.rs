@@ -0,0 +1,231 @@
+//! Tone generation for the sound timer.
+//!
+//! The interpreter only knows whether the buzzer is on ([`crate::Chip8::sound_active`]),
+//! and on XO-CHIP which [`AudioPattern`] to play; [`ToneGenerator`] turns that into samples
+//! which a frontend can play or, headless, collect into a buffer and write out with [`write_wav`].
+
+use std::f32::consts::PI;
+use std::io::{self, Write};
+
+/// Default sample rate for generated audio.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// Parses a waveform name as used on the command line, e.g. `"square"`.
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+
+    /// Value of the waveform at `phase` in [0, 1), in the range [-1, 1].
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+/// The sound of the buzzer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    /// Pitch in Hz.
+    pub frequency: f32,
+    /// Amplitude between 0.0 (silent) and 1.0 (full scale).
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// An XO-CHIP 1-bit audio pattern: 128 samples played in a loop at a rate set by `pitch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// The pitch before any `FX3A`, which plays the pattern at 4000 samples per second.
+    pub const DEFAULT_PITCH: u8 = 64;
+
+    /// Number of pattern bits played per second.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((f32::from(self.pitch) - 64.0) / 48.0)
+    }
+
+    /// The bit at `position` in [0, 128), most significant bit of the first byte first.
+    fn bit(&self, position: usize) -> bool {
+        self.pattern[position / 8] & (0x80 >> (position % 8)) != 0
+    }
+}
+
+/// Produces a continuous stream of samples that is audible while the sound timer is active.
+#[derive(Debug, Clone)]
+pub struct ToneGenerator {
+    tone: Tone,
+    sample_rate: u32,
+    phase: f32,
+    pattern: Option<AudioPattern>,
+}
+
+impl ToneGenerator {
+    pub fn new(tone: Tone, sample_rate: u32) -> ToneGenerator {
+        ToneGenerator {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            pattern: None,
+        }
+    }
+
+    /// Plays `pattern` instead of the configured tone, or goes back to the tone if `None`.
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the next sample, or silence if `active` is false.
+    pub fn next_sample(&mut self, active: bool) -> f32 {
+        if !active {
+            // Restart the period so every beep begins the same way.
+            self.phase = 0.0;
+            return 0.0;
+        }
+        match self.pattern {
+            Some(pattern) => {
+                // The phase runs over the whole 128 bit pattern rather than a single period.
+                let sample = if pattern.bit((self.phase * 128.0) as usize % 128) { 1.0 } else { -1.0 };
+                let step = pattern.playback_rate() / 128.0 / self.sample_rate as f32;
+                self.phase = (self.phase + step).fract();
+                sample * self.tone.volume
+            },
+            None => {
+                let sample = self.tone.waveform.sample(self.phase) * self.tone.volume;
+                self.phase = (self.phase + self.tone.frequency / self.sample_rate as f32).fract();
+                sample
+            },
+        }
+    }
+
+    /// Fills `buffer` with consecutive samples.
+    pub fn fill(&mut self, buffer: &mut [f32], active: bool) {
+        for sample in buffer.iter_mut() {
+            *sample = self.next_sample(active);
+        }
+    }
+}
+
+/// Writes mono `samples` in the range [-1, 1] as a 16-bit PCM WAV file.
+pub fn write_wav<W: Write>(mut writer: W, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // Size of the fmt chunk
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // Mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // Byte rate
+    writer.write_all(&2u16.to_le_bytes())?; // Block align
+    writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_while_inactive() {
+        let mut generator = ToneGenerator::new(Tone::default(), 8000);
+        let mut buffer = [1.0; 100];
+        generator.fill(&mut buffer, false);
+        assert!(buffer.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn square_wave_at_the_configured_pitch_and_volume() {
+        let tone = Tone { frequency: 1000.0, volume: 0.5, waveform: Waveform::Square };
+        let mut generator = ToneGenerator::new(tone, 8000);
+        let mut buffer = [0.0; 16];
+        generator.fill(&mut buffer, true);
+        // 8 samples per period: four high, four low.
+        assert_eq!(buffer[..8], [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+        assert_eq!(buffer[..8], buffer[8..]);
+    }
+
+    #[test]
+    fn every_beep_starts_at_the_beginning_of_a_period() {
+        let mut generator = ToneGenerator::new(Tone { waveform: Waveform::Sawtooth, ..Tone::default() }, 8000);
+        let first = generator.next_sample(true);
+        generator.next_sample(true);
+        generator.next_sample(false);
+        assert_eq!(generator.next_sample(true), first);
+    }
+
+    #[test]
+    fn pattern_replaces_the_tone() {
+        let mut generator = ToneGenerator::new(Tone { volume: 1.0, ..Tone::default() }, 4000);
+        let mut pattern = [0x00; 16];
+        pattern[0] = 0xF0;
+        generator.set_pattern(Some(AudioPattern { pattern, pitch: AudioPattern::DEFAULT_PITCH }));
+        let mut buffer = [0.0; 8];
+        generator.fill(&mut buffer, true);
+        // At the default pitch the pattern plays one bit per sample at 4000 Hz.
+        assert_eq!(buffer, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn wav_header_and_sample_count() {
+        let mut generator = ToneGenerator::new(Tone::default(), 8000);
+        let mut samples = vec![0.0; 800];
+        generator.fill(&mut samples, true);
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 8000, &samples).unwrap();
+
+        assert_eq!(wav.len(), 44 + 800 * 2);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], (36 + 1600u32).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[22..24], 1u16.to_le_bytes());
+        assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+        assert_eq!(wav[34..36], 16u16.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], 1600u32.to_le_bytes());
+        let first = i16::from_le_bytes([wav[44], wav[45]]);
+        assert_eq!(first, (0.25 * f32::from(i16::MAX)) as i16);
+    }
+}
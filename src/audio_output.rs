@@ -0,0 +1,104 @@
+//! Playback of the buzzer. Without the `audio` feature this is a stub that always fails to open.
+
+#[cfg(not(feature = "audio"))]
+use chip8_rust::audio::{AudioPattern, ToneGenerator};
+
+#[cfg(feature = "audio")]
+pub use self::rodio_output::AudioOutput;
+
+#[cfg(not(feature = "audio"))]
+pub struct AudioOutput;
+
+#[cfg(not(feature = "audio"))]
+impl AudioOutput {
+    pub fn new(_generator: ToneGenerator) -> Result<AudioOutput, String> {
+        Err(String::from("built without the \"audio\" feature"))
+    }
+
+    pub fn set_state(&self, _active: bool, _pattern: Option<AudioPattern>) {}
+}
+
+#[cfg(feature = "audio")]
+mod rodio_output {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chip8_rust::audio::{AudioPattern, ToneGenerator};
+    use rodio::{OutputStream, Sink, Source};
+
+    /// Plays the buzzer on the default output device for as long as it is switched on.
+    pub struct AudioOutput {
+        _stream: OutputStream,
+        _sink: Sink,
+        state: Arc<Mutex<BuzzerState>>,
+    }
+
+    #[derive(Default)]
+    struct BuzzerState {
+        active: bool,
+        pattern: Option<AudioPattern>,
+    }
+
+    impl AudioOutput {
+        pub fn new(generator: ToneGenerator) -> Result<AudioOutput, String> {
+            let (stream, handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
+            let sink = Sink::try_new(&handle).map_err(|e| e.to_string())?;
+            let state = Arc::new(Mutex::new(BuzzerState::default()));
+            sink.append(Buzzer {
+                generator,
+                state: state.clone(),
+            });
+            Ok(AudioOutput {
+                _stream: stream,
+                _sink: sink,
+                state,
+            })
+        }
+
+        /// Switches the buzzer on or off, playing `pattern` (XO-CHIP) instead of the tone if given.
+        pub fn set_state(&self, active: bool, pattern: Option<AudioPattern>) {
+            if let Ok(mut state) = self.state.lock() {
+                state.active = active;
+                state.pattern = pattern;
+            }
+        }
+    }
+
+    struct Buzzer {
+        generator: ToneGenerator,
+        state: Arc<Mutex<BuzzerState>>,
+    }
+
+    impl Iterator for Buzzer {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            let active = match self.state.lock() {
+                Ok(state) => {
+                    self.generator.set_pattern(state.pattern);
+                    state.active
+                },
+                Err(_) => false,
+            };
+            Some(self.generator.next_sample(active))
+        }
+    }
+
+    impl Source for Buzzer {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.generator.sample_rate()
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+}
@@ -1,32 +1,93 @@
+use std::error::Error;
+use std::fmt;
 use std::num::Wrapping;
 
+use crate::audio::AudioPattern;
+use crate::disasm::{self, Syntax};
+use crate::instruction::{decode, Instruction};
+use crate::quirks::Quirks;
+use crate::random::Random;
+use crate::rom::{self, RomError, RomWarning};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::trace::{CpuState, TraceEvent, Tracer};
+
+/// Width of the display in pixels.
+pub const SCREEN_WIDTH: usize = 64;
+/// Height of the display in pixels.
+pub const SCREEN_HEIGHT: usize = 32;
+/// Width of the SUPER-CHIP high resolution display in pixels.
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+/// Height of the SUPER-CHIP high resolution display in pixels.
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+/// Memory size of CHIP-8 and SUPER-CHIP.
+pub const MEMORY_SIZE: usize = 0x1000;
+/// Memory size of XO-CHIP.
+pub const XO_MEMORY_SIZE: usize = 0x10000;
+/// Address of the SUPER-CHIP 8x10 font, which follows the regular 4x5 font.
+pub const BIG_FONT_START: u16 = 0x50;
+/// Rate in Hz at which the delay and sound timers count down.
+pub const TIMER_FREQUENCY: u32 = 60;
+/// Number of instructions executed per timer tick unless configured otherwise.
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+/// Address at which programs are loaded and execution starts.
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Creates an interpreter with the font set and `rom_bytes` loaded into memory, seeding `CXNN`'s
+/// random number generator from the operating system.
+///
+/// Bytes that don't fit in memory are dropped; use [`Chip8::load_rom`] to have the ROM checked first.
 pub fn new(rom_bytes: &[u8]) -> Chip8 {
+    with_seed(rom_bytes, rand::random())
+}
+
+/// Like [`new`], but `CXNN` produces the same sequence for every run with the same `seed`.
+pub fn with_seed(rom_bytes: &[u8], seed: u64) -> Chip8 {
     let mut chip8 = Chip8 {
         op_code: 0,
-        memory: [0; 4096],
+        memory: [0; XO_MEMORY_SIZE],
         v: [0; 16],
         index: 0,
-        program_counter: 0x200,
-        gfx: [0; 64 * 32],
+        program_counter: PROGRAM_START,
+        gfx: [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+        hires: false,
+        planes: 0b01,
         delay_timer: 0,
         sound_timer: 0,
         stack: [0; 16],
         stack_pointer: 0,
         keys: [0; 16],
-        wait_keys: false,
-        draw: false
+        key_wait: None,
+        quirks: Quirks::default(),
+        variant: Variant::Chip8,
+        rpl_flags: [0; 16],
+        audio_pattern: None,
+        exited: false,
+        cycles: 0,
+        frames: 0,
+        tracer: None,
+        frame_done: false,
+        draw: false,
+        cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+        write_log: None,
+        seed,
+        random: Random::new(seed),
+        load_address: PROGRAM_START
     };
 
     let font_set = font_set();
     for (i, _byte) in font_set.iter().enumerate() {
         chip8.memory[i] = font_set[i];
     }
-
-    for (i, &byte) in rom_bytes.iter().enumerate() {
-        chip8.memory[i + 0x200] = byte;
+    let big_font_set = big_font_set();
+    for (i, &byte) in big_font_set.iter().enumerate() {
+        chip8.memory[i + BIG_FONT_START as usize] = byte;
     }
 
-    return chip8;
+    let rom_space = &mut chip8.memory[PROGRAM_START as usize..];
+    let size = rom_bytes.len().min(rom_space.len());
+    rom_space[..size].copy_from_slice(&rom_bytes[..size]);
+
+    chip8
 }
 
 fn font_set() -> [u8; 80] {
@@ -50,341 +111,1281 @@ fn font_set() -> [u8; 80] {
     ]
 }
 
+fn big_font_set() -> [u8; 160] {
+    [
+        0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+        0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+        0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+        0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+    ]
+}
+
+/// The instruction set being interpreted. Each variant is a superset of the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Variant {
+    /// The original CHIP-8 instruction set.
+    Chip8,
+    /// SUPER-CHIP 1.1: 128x64 high resolution mode, scrolling, 16x16 sprites, big font and RPL flags.
+    SuperChip,
+    /// XO-CHIP: 64 KB of memory, two bit-planes, register ranges, long I loads and audio patterns.
+    XoChip,
+}
+
+impl Variant {
+    /// Looks up a variant by name: `chip8`, `schip` or `xochip`.
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "chip8" | "chip-8" => Some(Variant::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Variant::SuperChip),
+            "xochip" | "xo-chip" => Some(Variant::XoChip),
+            _ => None
+        }
+    }
+
+    /// The name [`Variant::from_name`] accepts for this variant.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Chip8 => "chip8",
+            Variant::SuperChip => "schip",
+            Variant::XoChip => "xochip",
+        }
+    }
+}
+
+/// An error that stops execution. `address` is the address of the offending instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The instruction at `address` is not a known opcode.
+    UnknownOpcode { address: u16, op_code: u16 },
+    /// A subroutine call was made with all 16 stack slots in use.
+    StackOverflow { address: u16 },
+    /// A return was executed with an empty stack.
+    StackUnderflow { address: u16 },
+    /// An instruction tried to read or write `target`, which is outside of memory.
+    MemoryOutOfBounds { address: u16, target: usize },
+    /// The program counter points outside of memory.
+    ProgramCounterOutOfRange { address: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { address, op_code } => {
+                write!(f, "unknown opcode {:#06x} at {:#05x}", op_code, address)
+            },
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "stack overflow at {:#05x}", address)
+            },
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "return with empty stack at {:#05x}", address)
+            },
+            Chip8Error::MemoryOutOfBounds { address, target } => {
+                write!(f, "memory access out of bounds ({:#x}) at {:#05x}", target, address)
+            },
+            Chip8Error::ProgramCounterOutOfRange { address } => {
+                write!(f, "program counter out of range at {:#x}", address)
+            },
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// Packs the quirks into two bytes for save states, one bit per flag in declaration order.
+fn quirk_bits(quirks: Quirks) -> u16 {
+    quirks.flags().iter().enumerate().fold(0, |bits, (bit, &(_, enabled))| bits | (enabled as u16) << bit)
+}
+
+fn quirks_from_bits(bits: u16) -> Quirks {
+    let mut quirks = Quirks::default();
+    for (bit, (name, _)) in Quirks::default().flags().iter().enumerate() {
+        quirks.set_flag(name, bits & (1 << bit) != 0);
+    }
+    quirks
+}
+
+/// State of a pending `FX0A`: the register to store the key in, and the key seen going down so far.
+#[derive(Debug, Clone, Copy)]
+struct KeyWait {
+    register: usize,
+    pressed: Option<u8>,
+}
+
+/// A CHIP-8 virtual machine: memory, registers, timers, display and keypad.
 pub struct Chip8 {
     op_code: u16,
-    memory: [u8; 4096],
+    memory: [u8; XO_MEMORY_SIZE],
     v: [u8; 16],
     index: u16,
     program_counter: u16,
-    pub gfx: [u8; 64 * 32],
+    gfx: [u8; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+    hires: bool,
+    planes: u8,
     delay_timer: u8,
     sound_timer: u8,
     stack: [u16; 16],
     stack_pointer: u16,
-    pub keys: [u8; 16],
-    pub wait_keys: bool,
-    pub draw: bool
+    keys: [u8; 16],
+    key_wait: Option<KeyWait>,
+    quirks: Quirks,
+    variant: Variant,
+    rpl_flags: [u8; 16],
+    audio_pattern: Option<AudioPattern>,
+    exited: bool,
+    cycles: u64,
+    frames: u64,
+    tracer: Option<Tracer>,
+    frame_done: bool,
+    draw: bool,
+    cycles_per_frame: u32,
+    write_log: Option<Vec<usize>>,
+    seed: u64,
+    random: Random,
+    load_address: u16
 }
 
 impl Chip8 {
 
-    pub fn emulate_cycle(&mut self) {
-        self.fetch_opcode();
-        self.execute_opcode();
-        self.update_timers();
+    /// Fetches and executes a single instruction. Does nothing while an `FX0A` is waiting for a key
+    /// or after the program exited with `00FD`.
+    ///
+    /// Timers are not affected; call [`Chip8::tick_timers`] at `TIMER_FREQUENCY` independently of the CPU clock.
+    /// On error the machine is left at the offending instruction, so calling this again fails the same way.
+    pub fn emulate_cycle(&mut self) -> Result<(), Chip8Error> {
+        if self.key_wait.is_some() || self.exited {
+            return Ok(());
+        }
+        if let Some(writes) = &mut self.write_log {
+            writes.clear();
+        }
+        self.fetch_opcode()?;
+        let instruction = self.decode_opcode()?;
+        let address = self.program_counter;
+        let traced = match &self.tracer {
+            Some(tracer) => tracer.wants(address, self.op_code),
+            None => false,
+        };
+        if !traced {
+            self.execute(instruction)?;
+            self.cycles += 1;
+            return Ok(());
+        }
+
+        let long_address = match instruction {
+            Instruction::LoadIndexLong => self.long_operand().ok(),
+            _ => None,
+        };
+        let disassembly = disasm::format_instruction(instruction, Syntax::Octo, long_address, &|target| {
+            format!("{:#05x}", target)
+        });
+        let before = self.cpu_state();
+        let result = self.execute(instruction);
+        let after = self.cpu_state();
+        let event = TraceEvent {
+            cycle: self.cycles,
+            address,
+            op_code: self.op_code,
+            disassembly: &disassembly,
+            before: &before,
+            after: &after,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            if tracer.record(&event).is_err() {
+                // Stop tracing rather than failing the program when the trace output breaks.
+                tracer.set_enabled(false);
+            }
+        }
+        result?;
+        self.cycles += 1;
+        Ok(())
+    }
+
+    /// Copies `rom_bytes` into memory at `address` and starts execution there, e.g. at `PROGRAM_START`
+    /// or at 0x600 for ETI-660 programs. Set the variant first, since it decides how much memory there is.
+    ///
+    /// Fails without touching the machine if the ROM is empty or doesn't fit. Otherwise returns
+    /// warnings for data that doesn't look like a CHIP-8 program.
+    pub fn load_rom(&mut self, rom_bytes: &[u8], address: u16) -> Result<Vec<RomWarning>, RomError> {
+        let warnings = rom::check(rom_bytes, address, self.memory_size(), self.variant)?;
+        let start = address as usize;
+        self.memory[start..start + rom_bytes.len()].copy_from_slice(rom_bytes);
+        self.program_counter = address;
+        self.load_address = address;
+        Ok(warnings)
+    }
+
+    /// The address the ROM was loaded at and execution started from.
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// The seed the random number generator started from; pass it to [`with_seed`] to reproduce a run.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Number of timer ticks, i.e. frames, since the machine was created.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Number of instructions executed since the machine was created.
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Attaches a tracer that receives every executed instruction, or detaches it with `None`.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    fn cpu_state(&self) -> CpuState {
+        CpuState {
+            v: self.v,
+            index: self.index,
+            stack: self.stack().to_vec(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Runs `cycles` instructions, stopping early while waiting for a key press or on the first error.
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            if self.key_wait.is_some() || self.exited {
+                break;
+            }
+            self.emulate_cycle()?;
+        }
+        Ok(())
+    }
+
+    /// Runs one 1/`TIMER_FREQUENCY` second frame: `cycles_per_frame` instructions followed by a timer tick.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Like [`Chip8::run_frame`], but calls `stop` after every instruction and ends the frame early when it
+    /// returns true. Returns whether the frame was stopped this way; the timers are ticked either way.
+    pub fn run_frame_until<F: FnMut(&mut Chip8) -> bool>(&mut self, mut stop: F) -> Result<bool, Chip8Error> {
+        self.frame_done = false;
+        let mut stopped = false;
+        for _ in 0..self.cycles_per_frame {
+            if self.frame_done || self.key_wait.is_some() || self.exited {
+                break;
+            }
+            self.emulate_cycle()?;
+            if stop(self) {
+                stopped = true;
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(stopped)
+    }
+
+    /// Number of instructions [`Chip8::run_frame`] executes, i.e. the CPU clock in units of `TIMER_FREQUENCY` Hz.
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    /// Sets the CPU clock to `cycles_per_frame * TIMER_FREQUENCY` instructions per second. Must be at least 1.
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.cycles_per_frame = cycles_per_frame.max(1);
+    }
+
+    /// Counts the delay and sound timers down by one. Should be called `TIMER_FREQUENCY` times per second.
+    pub fn tick_timers(&mut self) {
+        self.frames += 1;
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// Returns whether the buzzer should sound, i.e. the sound timer is non-zero.
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// The display as `screen_width() * screen_height()` pixels in row-major order.
+    ///
+    /// Each pixel holds one bit per bit-plane, so it is 0 or 1 except on XO-CHIP, where the two planes
+    /// give four colours 0-3.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.gfx[..self.screen_width() * self.screen_height()]
+    }
+
+    /// Width of the display in the current resolution.
+    pub fn screen_width(&self) -> usize {
+        if self.hires { HIRES_SCREEN_WIDTH } else { SCREEN_WIDTH }
+    }
+
+    /// Height of the display in the current resolution.
+    pub fn screen_height(&self) -> usize {
+        if self.hires { HIRES_SCREEN_HEIGHT } else { SCREEN_HEIGHT }
+    }
+
+    /// Returns whether the SUPER-CHIP high resolution mode is enabled.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// The audio pattern loaded by XO-CHIP's `F002`, played instead of a plain tone while the sound timer runs.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_pattern
+    }
+
+    /// Returns whether the program has exited with `00FD`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Returns whether the display changed since the last call, clearing the flag.
+    pub fn take_draw_flag(&mut self) -> bool {
+        let draw = self.draw;
+        self.draw = false;
+        draw
+    }
+
+    /// Marks hex key `key` (0x0-0xF) as pressed or released, completing a pending `FX0A` if applicable.
+    /// Only the low nibble of `key` is used.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        let was_pressed = self.keys[key as usize] != 0;
+        self.keys[key as usize] = pressed as u8;
+        if let Some(mut key_wait) = self.key_wait {
+            if pressed && !was_pressed && key_wait.pressed.is_none() {
+                key_wait.pressed = Some(key);
+            }
+            let done = if self.quirks.wait_for_key_release {
+                !pressed && key_wait.pressed == Some(key)
+            } else {
+                key_wait.pressed.is_some()
+            };
+            if done {
+                self.v[key_wait.register] = key;
+                self.key_wait = None;
+            } else {
+                self.key_wait = Some(key_wait);
+            }
+        }
+    }
+
+    /// Returns whether hex key `key` (0x0-0xF) is currently pressed. Only the low nibble of `key` is used.
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize] != 0
+    }
+
+    /// Returns whether execution is blocked on `FX0A` until a key is pressed (or released).
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    /// The interpreter behaviours currently emulated.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// The instruction set currently interpreted.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    /// The general purpose registers V0-VF.
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    /// Sets register V`register` (0-F) to `value`.
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.v[register & 0xF] = value;
+    }
+
+    /// The address register I.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address;
+    }
+
+    /// The return addresses currently on the call stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+
+    /// The delay timer, counting down to zero.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    /// The sound timer; a tone should sound while it is non-zero.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// The address space (4 KB, or 64 KB on XO-CHIP), including the font set and the loaded program.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.memory_size()]
+    }
+
+    /// Overwrites the byte at `target`, returning false when it lies outside the address space.
+    /// Unlike writes made by the program, this is not reported by [`Chip8::memory_writes`].
+    pub fn set_memory(&mut self, target: usize, byte: u8) -> bool {
+        let memory_size = self.memory_size();
+        match self.memory[..memory_size].get_mut(target) {
+            Some(cell) => {
+                *cell = byte;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Starts or stops recording the addresses each instruction writes to, see [`Chip8::memory_writes`].
+    pub fn set_write_logging(&mut self, enabled: bool) {
+        self.write_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// The addresses written by the last instruction executed, if write logging is enabled.
+    pub fn memory_writes(&self) -> &[usize] {
+        self.write_log.as_deref().unwrap_or(&[])
+    }
+
+    /// Serializes the complete machine state, including the quirks, variant and speed, in the versioned
+    /// format described in [`crate::state`]. The tracer belongs to the host and is not saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(self.variant as u8);
+        writer.u16(quirk_bits(self.quirks));
+        writer.u32(self.cycles_per_frame);
+        writer.u32(self.memory_size() as u32);
+        writer.bytes(self.memory());
+        writer.bytes(&self.v);
+        writer.u16(self.index);
+        writer.u16(self.program_counter);
+        writer.u16(self.op_code);
+        for &address in self.stack.iter() {
+            writer.u16(address);
+        }
+        writer.u16(self.stack_pointer);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bytes(&self.gfx);
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        writer.bytes(&self.keys);
+        match self.key_wait {
+            Some(KeyWait { register, pressed }) => {
+                writer.bool(true);
+                writer.u8(register as u8);
+                writer.u8(pressed.unwrap_or(0xFF));
+            },
+            None => writer.bool(false),
+        }
+        writer.bytes(&self.rpl_flags);
+        match self.audio_pattern {
+            Some(AudioPattern { pattern, pitch }) => {
+                writer.bool(true);
+                writer.bytes(&pattern);
+                writer.u8(pitch);
+            },
+            None => writer.bool(false),
+        }
+        writer.bool(self.exited);
+        writer.u64(self.cycles);
+        writer.u64(self.frames);
+        writer.u64(self.seed);
+        writer.u64(self.random.state());
+        writer.finish()
+    }
+
+    /// Restores a state written by [`Chip8::save_state`]. On error the machine is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state)?;
+        let mut restored = with_seed(&[], 0);
+        restored.variant = match reader.u8()? {
+            0 => Variant::Chip8,
+            1 => Variant::SuperChip,
+            2 => Variant::XoChip,
+            _ => return Err(StateError::Invalid("variant")),
+        };
+        restored.quirks = quirks_from_bits(reader.u16()?);
+        restored.cycles_per_frame = reader.u32()?.max(1);
+        let memory_size = reader.u32()? as usize;
+        if memory_size != restored.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        restored.memory[..memory_size].copy_from_slice(reader.bytes(memory_size)?);
+        restored.v.copy_from_slice(reader.bytes(16)?);
+        restored.index = reader.u16()?;
+        restored.program_counter = reader.u16()?;
+        restored.op_code = reader.u16()?;
+        for address in restored.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        restored.stack_pointer = reader.u16()?;
+        if restored.stack_pointer as usize > restored.stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        restored.delay_timer = reader.u8()?;
+        restored.sound_timer = reader.u8()?;
+        restored.gfx.copy_from_slice(reader.bytes(HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT)?);
+        restored.hires = reader.bool()?;
+        restored.planes = reader.u8()?;
+        restored.keys.copy_from_slice(reader.bytes(16)?);
+        if reader.bool()? {
+            let register = reader.u8()? as usize;
+            let pressed = match reader.u8()? {
+                0xFF => None,
+                key => Some(key),
+            };
+            if register > 0xF || pressed.is_some_and(|key| key > 0xF) {
+                return Err(StateError::Invalid("key wait"));
+            }
+            restored.key_wait = Some(KeyWait { register, pressed });
+        }
+        restored.rpl_flags.copy_from_slice(reader.bytes(16)?);
+        if reader.bool()? {
+            let mut pattern = [0; 16];
+            pattern.copy_from_slice(reader.bytes(16)?);
+            restored.audio_pattern = Some(AudioPattern { pattern, pitch: reader.u8()? });
+        }
+        restored.exited = reader.bool()?;
+        restored.cycles = reader.u64()?;
+        restored.frames = reader.u64()?;
+        restored.seed = reader.u64()?;
+        restored.random = Random::new(reader.u64()?);
+        reader.finish()?;
+
+        restored.tracer = self.tracer.take();
+        restored.write_log = self.write_log.take();
+        restored.load_address = self.load_address;
+        restored.draw = true;
+        *self = restored;
+        Ok(())
+    }
+
+    /// Moves I on after `FX55`/`FX65` with registers V0 to VX, as far as the quirks say.
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        if self.quirks.load_store_increments_index {
+            let increment = if self.quirks.load_store_index_off_by_one { x as u16 } else { x as u16 + 1 };
+            self.index = self.index.wrapping_add(increment);
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        if self.variant == Variant::XoChip { XO_MEMORY_SIZE } else { MEMORY_SIZE }
     }
 
-    fn fetch_opcode(&mut self) {
-        let first_byte = self.memory[self.program_counter as usize];
-        let second_byte = self.memory[self.program_counter as usize + 1];
+    fn fetch_opcode(&mut self) -> Result<(), Chip8Error> {
+        let address = self.program_counter as usize;
+        if address + 1 >= self.memory_size() {
+            return Err(Chip8Error::ProgramCounterOutOfRange { address: self.program_counter });
+        }
+        let first_byte = self.memory[address];
+        let second_byte = self.memory[address + 1];
         self.op_code = (first_byte as u16) << 8 | second_byte as u16;
+        Ok(())
     }
 
-    fn execute_opcode(&mut self) {
-        let op_code = self.op_code;
-        Chip8::print_debug(&format!("OP: {:#06x}", self.op_code));
-        match self.op_code & 0xF000 {
-            0x0000 => {
-                match op_code & 0x000F {
-                    0x0000 => {
-                        Chip8::print_debug(&format!("0x00E0: Clear screen"));
-                        self.gfx = [0; 64 * 32];
-                        self.draw = true;
-                        self.program_counter += 2;
-                    },
-                    0x000E => {
-                        Chip8::print_debug(&format!("0x00EE: Return from subroutine"));
-                        self.stack_pointer -= 1;
-                        self.program_counter = self.stack[self.stack_pointer as usize];
-                        self.program_counter += 2;
-                    },
-                    _ => {
-                        Chip8::print_debug(&format!("Unrecognized op code: {:X?}", op_code));
-                        return;
-                    }
+    fn read_memory(&self, target: usize) -> Result<u8, Chip8Error> {
+        match self.memory[..self.memory_size()].get(target) {
+            Some(&byte) => Ok(byte),
+            None => Err(Chip8Error::MemoryOutOfBounds { address: self.program_counter, target }),
+        }
+    }
+
+    fn write_memory(&mut self, target: usize, byte: u8) -> Result<(), Chip8Error> {
+        let address = self.program_counter;
+        let memory_size = self.memory_size();
+        match self.memory[..memory_size].get_mut(target) {
+            Some(cell) => {
+                *cell = byte;
+                if let Some(writes) = &mut self.write_log {
+                    writes.push(target);
+                }
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds { address, target }),
+        }
+    }
+
+    /// Decodes `self.op_code`, rejecting instructions the current variant does not support.
+    fn decode_opcode(&self) -> Result<Instruction, Chip8Error> {
+        let unknown = Chip8Error::UnknownOpcode { address: self.program_counter, op_code: self.op_code };
+        match decode(self.op_code) {
+            Ok(instruction) if instruction.variant() <= self.variant => Ok(instruction),
+            _ => Err(unknown),
+        }
+    }
+
+    /// Executes `instruction` as if it were stored at the program counter.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        match instruction {
+            Instruction::ClearScreen => {
+                self.clear_screen();
+                self.advance(2)?;
+            },
+            Instruction::Return => {
+                if self.stack_pointer == 0 {
+                    return Err(Chip8Error::StackUnderflow { address: self.program_counter });
                 }
+                self.stack_pointer -= 1;
+                self.program_counter = self.stack[self.stack_pointer as usize];
+                self.advance(2)?;
+            },
+            Instruction::ScrollDown { n } => {
+                self.scroll(0, n as isize);
+                self.advance(2)?;
+            },
+            Instruction::ScrollUp { n } => {
+                self.scroll(0, -(n as isize));
+                self.advance(2)?;
+            },
+            Instruction::ScrollRight => {
+                self.scroll(4, 0);
+                self.advance(2)?;
             },
-            0x1000 => {
-                Chip8::print_debug(&format!("0x1NNN: Jumps to address NNN"));
-                self.program_counter = op_code & 0x0FFF;
+            Instruction::ScrollLeft => {
+                self.scroll(-4, 0);
+                self.advance(2)?;
             },
-            0x2000 => {
-                Chip8::print_debug(&format!("0x2NNN: Calls subroutine at NNN"));
+            Instruction::Exit => {
+                self.exited = true;
+            },
+            Instruction::LowRes | Instruction::HighRes => {
+                self.hires = instruction == Instruction::HighRes;
+                self.gfx = [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT];
+                self.draw = true;
+                self.advance(2)?;
+            },
+            Instruction::Jump { nnn } => {
+                self.program_counter = nnn;
+            },
+            Instruction::Call { nnn } => {
+                if self.stack_pointer as usize == self.stack.len() {
+                    return Err(Chip8Error::StackOverflow { address: self.program_counter });
+                }
                 self.stack[self.stack_pointer as usize] = self.program_counter;
                 self.stack_pointer += 1;
-                self.program_counter = op_code & 0x0FFF;
+                self.program_counter = nnn;
             },
-            0x3000 => {
-                Chip8::print_debug(&format!("0x3XNN: Skips the next instruction if VX equals NN. (Usually the next instruction is a jump to skip a code block)"));
-                if self.v[((op_code & 0x0F00) >> 8) as usize] == (op_code & 0x00FF) as u8 {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
+            Instruction::SkipEqImm { x, nn } => {
+                self.skip_if(self.v[x as usize] == nn)?;
             },
-            0x4000 => {
-                Chip8::print_debug(&format!("0x4XNN: Skips the next instruction if VX doesn't equal NN"));
-                if self.v[((op_code & 0x0F00) >> 8) as usize] != (op_code & 0x00FF) as u8 {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
+            Instruction::SkipNotEqImm { x, nn } => {
+                self.skip_if(self.v[x as usize] != nn)?;
+            },
+            Instruction::SkipEqReg { x, y } => {
+                self.skip_if(self.v[x as usize] == self.v[y as usize])?;
+            },
+            Instruction::SkipNotEqReg { x, y } => {
+                self.skip_if(self.v[x as usize] != self.v[y as usize])?;
+            },
+            Instruction::SaveRange { x, y } => {
+                for (offset, register) in Chip8::register_range(x, y).enumerate() {
+                    self.write_memory(self.index as usize + offset, self.v[register])?;
                 }
+                self.advance(2)?;
             },
-            0x5000 => {
-                Chip8::print_debug(&format!("0x5XY0: Skips the next instruction if VX equals VY"));
-                if self.v[((op_code & 0x0F00) >> 8) as usize] == self.v[((op_code & 0x00F0) >> 4) as usize] {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
+            Instruction::LoadRange { x, y } => {
+                for (offset, register) in Chip8::register_range(x, y).enumerate() {
+                    self.v[register] = self.read_memory(self.index as usize + offset)?;
                 }
-            }
-            0x6000 => {
-                Chip8::print_debug(&format!("0x6XNN: Sets VX to NN"));
-                self.v[((op_code & 0x0F00) >> 8) as usize] = (op_code & 0x00FF) as u8;
-                self.program_counter += 2;
-            },
-            0x7000 => {
-                Chip8::print_debug(&format!("0x7XNN: Adds NN to VX. (Carry flag is not changed)"));
-                let vx = Wrapping(self.v[((op_code & 0x0F00) >> 8) as usize]);
-                let nn = Wrapping((op_code & 0x00FF) as u8);
-                self.v[((op_code & 0x0F00) >> 8) as usize] = (vx + nn).0;
-                self.program_counter += 2;
-            },
-            0x8000 => {
-                match op_code & 0x000F {
-                    0x0000 => {
-                        Chip8::print_debug(&format!("0x8XY0: Sets VX to the value of VY"));
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = self.v[((op_code & 0x00F0) >> 4) as usize];
-                        self.program_counter += 2;
-                    },
-                    0x0001 => {
-                        Chip8::print_debug(&format!("0x8XY1: Sets VX to VX or VY. (Bitwise OR operation)"));
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = self.v[((op_code & 0x0F00) >> 8) as usize] | self.v[((op_code & 0x00F0) >> 4) as usize];
-                        self.program_counter += 2;
-                    }
-                    0x0002 => {
-                        Chip8::print_debug(&format!("0x8XY2: Sets VX to VX and VY. (Bitwise AND operation)"));
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = self.v[((op_code & 0x0F00) >> 8) as usize] & self.v[((op_code & 0x00F0) >> 4) as usize];
-                        self.program_counter += 2;
-                    },
-                    0x0003 => {
-                        Chip8::print_debug(&format!("0x8XY3: Sets VX to VX xor VY"));
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = self.v[((op_code & 0x0F00) >> 8) as usize] ^ self.v[((op_code & 0x00F0) >> 4) as usize];
-                        self.program_counter += 2;
-                    },
-                    0x0004 => {
-                        Chip8::print_debug(&format!("0x8XY4: Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't"));
-                        if self.v[((op_code & 0x00F0) >> 4) as usize] > (0xFF - self.v[((op_code & 0x0F00) >> 8) as usize]) {
-                            self.v[0xF] = 1;
-                        } else {
-                            self.v[0xF] = 0;
-                        }
-                        let vx = Wrapping(self.v[((op_code & 0x0F00) >> 8) as usize]);
-                        let vy = Wrapping(self.v[((op_code & 0x00F0) >> 4) as usize]);
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = (vx + vy).0;
-                        self.program_counter += 2;
-                    },
-                    0x0005 => {
-                        Chip8::print_debug(&format!("0x8XY5: VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there isn't"));
-                        if self.v[((op_code & 0x0F00) >> 8) as usize] < self.v[((op_code & 0x00F0) >> 4) as usize] {
-                            self.v[0xF] = 0;
-                        } else {
-                            self.v[0xF] = 1;
-                        }
-                        let vx = Wrapping(self.v[((op_code & 0x0F00) >> 8) as usize]);
-                        let vy = Wrapping(self.v[((op_code & 0x00F0) >> 4) as usize]);
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = (vx - vy).0;
-                        self.program_counter += 2;
-                    },
-                    0x0006 => {
-                        Chip8::print_debug(&format!("0x8XY6: Stores the least significant bit of VX in VF and then shifts VX to the right by 1"));
-                        self.v[0xF] = self.v[((op_code & 0x0F00) >> 8) as usize] & 0x1;
-                        self.v[((op_code & 0x0F00) >> 8) as usize] >>= 1;
-                        self.program_counter += 2;
-                    }
-                    0x0007 => {
-                        Chip8::print_debug(&format!("0x8XY7: Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there isn't"));
-                        if self.v[((op_code & 0x00F0) >> 4) as usize] < self.v[((op_code & 0x0F00) >> 8) as usize] {
-                            self.v[0xF] = 0;
-                        } else {
-                            self.v[0xF] = 1;
-                        }
-                        let vy = Wrapping(self.v[((op_code & 0x00F0) >> 4) as usize]);
-                        let vx = Wrapping(self.v[((op_code & 0x0F00) >> 8) as usize]);
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = (vy - vx).0;
-                        self.program_counter += 2;
-                    }
-                    0x000E => {
-                        Chip8::print_debug(&format!("0x8XYE: Stores the most significant bit of VX in VF and then shifts VX to the left by 1"));
-                        self.v[0xF] = self.v[((op_code & 0x0F00) >> 8) as usize] >> 7;
-                        self.v[((op_code & 0x0F00) >> 8) as usize] <<= 1;
-                        self.program_counter += 2;
-                    }
-                    _ => {
-                        Chip8::print_debug(&format!("Unrecognized op code: {:X?}", op_code));
-                        return;
-                    }
+                self.advance(2)?;
+            },
+            Instruction::LoadImm { x, nn } => {
+                self.v[x as usize] = nn;
+                self.advance(2)?;
+            },
+            Instruction::AddImm { x, nn } => {
+                // The carry flag is not changed
+                self.v[x as usize] = (Wrapping(self.v[x as usize]) + Wrapping(nn)).0;
+                self.advance(2)?;
+            },
+            Instruction::Move { x, y } => {
+                self.v[x as usize] = self.v[y as usize];
+                self.advance(2)?;
+            },
+            Instruction::Or { x, y } => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.logic_vf_reset();
+                self.advance(2)?;
+            },
+            Instruction::And { x, y } => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.logic_vf_reset();
+                self.advance(2)?;
+            },
+            Instruction::Xor { x, y } => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.logic_vf_reset();
+                self.advance(2)?;
+            },
+            Instruction::Add { x, y } => {
+                // VF is set to 1 when there's a carry, and to 0 when there isn't
+                self.v[0xF] = (self.v[y as usize] > 0xFF - self.v[x as usize]) as u8;
+                self.v[x as usize] = (Wrapping(self.v[x as usize]) + Wrapping(self.v[y as usize])).0;
+                self.advance(2)?;
+            },
+            Instruction::Sub { x, y } => {
+                // VF is set to 0 when there's a borrow, and 1 when there isn't
+                self.v[0xF] = (self.v[x as usize] >= self.v[y as usize]) as u8;
+                self.v[x as usize] = (Wrapping(self.v[x as usize]) - Wrapping(self.v[y as usize])).0;
+                self.advance(2)?;
+            },
+            Instruction::SubReverse { x, y } => {
+                self.v[0xF] = (self.v[y as usize] >= self.v[x as usize]) as u8;
+                self.v[x as usize] = (Wrapping(self.v[y as usize]) - Wrapping(self.v[x as usize])).0;
+                self.advance(2)?;
+            },
+            Instruction::ShiftRight { x, y } => {
+                let source = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = source >> 1;
+                self.v[0xF] = source & 0x1;
+                self.advance(2)?;
+            },
+            Instruction::ShiftLeft { x, y } => {
+                let source = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = source << 1;
+                self.v[0xF] = source >> 7;
+                self.advance(2)?;
+            },
+            Instruction::LoadIndex { nnn } => {
+                self.index = nnn;
+                self.advance(2)?;
+            },
+            Instruction::JumpOffset { nnn } => {
+                let offset = if self.quirks.jump_uses_vx { self.v[(nnn >> 8) as usize] } else { self.v[0x0] };
+                self.program_counter = (offset as u16) + nnn;
+            },
+            Instruction::Random { x, nn } => {
+                self.v[x as usize] = self.random.next_byte() & nn;
+                self.advance(2)?;
+            },
+            Instruction::Draw { x, y, n } => {
+                self.draw_sprite(self.v[x as usize], self.v[y as usize], n)?;
+                self.draw = true;
+                self.frame_done = self.quirks.display_wait;
+                self.advance(2)?;
+            },
+            Instruction::SkipKeyPressed { x } => {
+                self.skip_if(self.keys[(self.v[x as usize] & 0xF) as usize] != 0)?;
+            },
+            Instruction::SkipKeyNotPressed { x } => {
+                self.skip_if(self.keys[(self.v[x as usize] & 0xF) as usize] == 0)?;
+            },
+            Instruction::LoadIndexLong => {
+                self.index = self.long_operand()?;
+                self.advance(4)?;
+            },
+            Instruction::SelectPlanes { n } => {
+                self.planes = n & 0b11;
+                self.advance(2)?;
+            },
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_memory(self.index as usize + offset)?;
                 }
-            }
-            0x9000 => {
-                Chip8::print_debug(&format!("0x9XY0: Skips the next instruction if VX doesn't equal VY"));
-                if self.v[((op_code & 0x0F00) >> 8) as usize] != self.v[((op_code & 0x00F0) >> 4) as usize] {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
+                let pitch = self.audio_pattern.map_or(AudioPattern::DEFAULT_PITCH, |current| current.pitch);
+                self.audio_pattern = Some(AudioPattern { pattern, pitch });
+                self.advance(2)?;
+            },
+            Instruction::SetPitch { x } => {
+                let pitch = self.v[x as usize];
+                let pattern = self.audio_pattern.map_or([0; 16], |current| current.pattern);
+                self.audio_pattern = Some(AudioPattern { pattern, pitch });
+                self.advance(2)?;
+            },
+            Instruction::GetDelay { x } => {
+                self.v[x as usize] = self.delay_timer;
+                self.advance(2)?;
+            },
+            Instruction::WaitKey { x } => {
+                // Blocks until the key is pressed (or released); see `set_key`.
+                self.key_wait = Some(KeyWait { register: x as usize, pressed: None });
+                self.advance(2)?;
+            },
+            Instruction::SetDelay { x } => {
+                self.delay_timer = self.v[x as usize];
+                self.advance(2)?;
+            },
+            Instruction::SetSound { x } => {
+                self.sound_timer = self.v[x as usize];
+                self.advance(2)?;
+            },
+            Instruction::AddIndex { x } => {
+                // VF is set to 1 when there is a range overflow (I+VX>0xFFF), and to 0 when there isn't
+                let vx = self.v[x as usize] as u16;
+                self.v[0xF] = (vx > 0xFFF_u16.saturating_sub(self.index)) as u8;
+                self.index = self.index.wrapping_add(vx);
+                self.advance(2)?;
+            },
+            Instruction::LoadFont { x } => {
+                let character = self.v[x as usize];
+                self.index = character as u16 * 5; // Each char takes 5 bytes
+                self.advance(2)?;
+            },
+            Instruction::LoadBigFont { x } => {
+                let character = self.v[x as usize] & 0xF;
+                self.index = BIG_FONT_START + character as u16 * 10; // Each char takes 10 bytes
+                self.advance(2)?;
+            },
+            Instruction::StoreBcd { x } => {
+                let vx = self.v[x as usize];
+                self.write_memory(self.index as usize, vx / 100)?;
+                self.write_memory(self.index as usize + 1, (vx / 10) % 10)?;
+                self.write_memory(self.index as usize + 2, vx % 10)?;
+                self.advance(2)?;
+            },
+            Instruction::Store { x } => {
+                for i in 0..=x as usize {
+                    self.write_memory(self.index as usize + i, self.v[i])?;
                 }
-            }
-            0xA000 => {
-                Chip8::print_debug(&format!("0xANNN: Sets I to the address NNN"));
-                self.index = op_code & 0x0FFF;
-                self.program_counter += 2;
-            },
-            0xB000 => {
-                Chip8::print_debug(&format!("0xBNNN: Jumps to the address NNN plus V0"));
-                self.program_counter = ((self.v[0x0] as u16) + (op_code & 0x0FFF));
-            }
-            0xC000 => {
-                Chip8::print_debug(&format!("0xCXNN: Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN"));
-                use rand::Rng;
-                let mut rng = rand::thread_rng();
-                self.v[((op_code & 0x0F00) >> 8) as usize] = rng.gen::<u8>() & ((op_code & 0x00FF) as u8);
-                self.program_counter += 2;
-            }
-            0xD000 => {
-                Chip8::print_debug(&format!("0xDXYN: Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels"));
-                let x = self.v[((op_code & 0x0F00) >> 8) as usize] as u16;
-                let y = self.v[((op_code & 0x00F0) >> 4) as usize] as u16;
-                let height = op_code & 0x000F;
-
-                self.v[0xF] = 0;
-                for y_line in 0..height {
-                    let pixel = self.memory[(self.index + y_line) as usize];
-                    for x_line in 0..8 {
-                        if (pixel & (0x80 >> x_line)) != 0 {
-                            if self.gfx[((x + x_line + ((y + y_line) * 64)) % (64 * 32)) as usize] == 1 {
-                                self.v[0xF] = 1;
-                            }
-                            self.gfx[((x + x_line + ((y + y_line) * 64)) % (64 * 32)) as usize] ^= 1;
-                        }
-                    }
+                self.increment_index_after_load_store(x);
+                self.advance(2)?;
+            },
+            Instruction::Load { x } => {
+                for i in 0..=x as usize {
+                    self.v[i] = self.read_memory(self.index as usize + i)?;
                 }
+                self.increment_index_after_load_store(x);
+                self.advance(2)?;
+            },
+            Instruction::SaveFlags { x } => {
+                let x = x as usize;
+                self.rpl_flags[..=x].copy_from_slice(&self.v[..=x]);
+                self.advance(2)?;
+            },
+            Instruction::LoadFlags { x } => {
+                let x = x as usize;
+                self.v[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+                self.advance(2)?;
+            },
+        }
+        Ok(())
+    }
 
-                self.draw = true;
-                self.program_counter += 2;
-            },
-            0xE000 => {
-                match op_code & 0x00FF {
-                    0x009E => {
-                        Chip8::print_debug(&format!("0xEX9E: Skips the next instruction if the key stored in VX is pressed"));
-                        if self.keys[self.v[((op_code & 0x0F00) >> 8) as usize] as usize] != 0 {
-                            self.program_counter += 4;
-                        } else {
-                            self.program_counter += 2;
-                        }
-                    },
-                    0x00A1 => {
-                        Chip8::print_debug(&format!("0xEXA1: Skips the next instruction if the key stored in VX isn't pressed"));
-                        if self.keys[self.v[((op_code & 0x0F00) >> 8) as usize] as usize] == 0 {
-                            self.program_counter += 4;
-                        } else {
-                            self.program_counter += 2;
-                        }
-                    },
-                    _ => {
-                        Chip8::print_debug(&format!("Unrecognized op code: {:X?}", op_code));
-                        return;
-                    }
+    /// Draws the sprite at I with its top left corner at (`x`, `y`), setting VF on collision.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        let width = self.screen_width();
+        let screen_height = self.screen_height();
+        // The starting position always wraps; pixels running off an edge wrap or clip depending on the quirks.
+        let x = x as usize % width;
+        let y = y as usize % screen_height;
+        let (sprite_width, height) = match n {
+            0 if self.variant >= Variant::SuperChip => (16, 16),
+            n => (8, n as usize),
+        };
+        let bytes_per_row = sprite_width / 8;
+
+        self.v[0xF] = 0;
+        // Each selected bit-plane consumes its own copy of the sprite data, one after the other.
+        let mut address = self.index as usize;
+        let planes = self.planes;
+        for plane in (0..2).map(|plane| 1u8 << plane).filter(|plane| planes & plane != 0) {
+            for y_line in 0..height {
+                let mut pixel: u16 = 0;
+                for _ in 0..bytes_per_row {
+                    pixel = (pixel << 8) | self.read_memory(address)? as u16;
+                    address += 1;
                 }
-            }
-            0xF000 => {
-                match op_code & 0x00FF {
-                    0x0007 => {
-                        Chip8::print_debug(&format!("0xFX07: Sets VX to the value of the delay timer"));
-                        self.v[((op_code & 0x0F00) >> 8) as usize] = self.delay_timer;
-                        self.program_counter += 2;
-                    },
-                    0x000A => {
-                        Chip8::print_debug(&format!("0xFX0A: A key press is awaited, and then stored in VX. (Blocking Operation. All instruction halted until next key event)"));
-                        self.wait_keys = true;
-                        self.program_counter += 2;
+                let mut pixel_y = y + y_line;
+                if pixel_y >= screen_height {
+                    if !self.quirks.wrap_sprites_y {
+                        continue;
                     }
-                    0x0015 => {
-                        Chip8::print_debug(&format!("0xFX15: Sets the delay timer to VX"));
-                        self.delay_timer = self.v[((op_code & 0x0F00) >> 8) as usize];
-                        self.program_counter += 2;
-                    },
-                    0x0018 => {
-                        Chip8::print_debug(&format!("0xFX18: Sets the sound timer to VX"));
-                        self.sound_timer = self.v[((op_code & 0x0F00) >> 8) as usize];
-                        self.program_counter += 2;
-                    },
-                    0x001E => {
-                        Chip8::print_debug(&format!("0xFX1E: Adds VX to I. VF is set to 1 when there is a range overflow (I+VX>0xFFF), and to 0 when there isn't"));
-                        if (self.v[((op_code & 0x0F00) >> 8) as usize]) as u16 > (0xFFF - self.index) {
-                            self.v[0xF] = 1;
-                        } else {
-                            self.v[0xF] = 0;
-                        }
-                        self.index += self.v[((op_code & 0x0F00) >> 8) as usize] as u16;
-                        self.program_counter += 2;
-                    },
-                    0x0029 => {
-                        Chip8::print_debug(&format!("0xFX29: Sets I to the location of the sprite for the character in VX"));
-                        let character = self.v[((op_code & 0x0F00) >> 8) as usize];
-                        self.index = (character * 5).into(); // Each char takes 5 bytes
-                        self.program_counter += 2;
-                    },
-                    0x0033 => {
-                        Chip8::print_debug(&format!("0xFX33: Stores the binary-coded decimal representation of VX"));
-                        self.memory[self.index as usize] = self.v[((op_code & 0x0F00) >> 8) as usize] / 100;
-                        self.memory[(self.index + 1) as usize] = (self.v[((op_code & 0x0F00) >> 8) as usize] / 10) % 10;
-                        self.memory[(self.index + 2) as usize] = (self.v[((op_code & 0x0F00) >> 8) as usize] % 100) % 10;
-                        self.program_counter += 2;
-                    },
-                    0x0055 => {
-                        Chip8::print_debug(&format!("0xFX55: Stores V0 to VX (including VX) in memory starting at address I"));
-                        for i in 0x0..(((op_code & 0x0F00) >> 8) + 1) {
-                            self.memory[(self.index + i) as usize] = self.v[i as usize];
+                    pixel_y %= screen_height;
+                }
+                for x_line in 0..sprite_width {
+                    let mut pixel_x = x + x_line;
+                    if pixel_x >= width {
+                        if !self.quirks.wrap_sprites_x {
+                            break;
                         }
-                        self.program_counter += 2;
+                        pixel_x %= width;
                     }
-                    0x0065 => {
-                        Chip8::print_debug(&format!("0xFX65: Fills V0 to VX (including VX) with values from memory starting at address I"));
-                        for i in 0x0..(((op_code & 0x0F00) >> 8) + 1) {
-                            self.v[i as usize] = self.memory[(self.index + i) as usize];
+                    if (pixel & (1 << (sprite_width - 1 - x_line))) != 0 {
+                        let offset = pixel_x + pixel_y * width;
+                        if self.gfx[offset] & plane != 0 {
+                            self.v[0xF] = 1;
                         }
-                        self.program_counter += 2;
-                    }
-                    _ => {
-                        Chip8::print_debug(&format!("Unrecognized op code: {:X?}", op_code));
-                        return;
+                        self.gfx[offset] ^= plane;
                     }
                 }
-            },
-            _ => {
-                Chip8::print_debug(&format!("Unrecognized op code: {:X?}", op_code));
-                return;
             }
         }
+        Ok(())
     }
 
-    fn update_timers(&mut self) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+    /// The 16-bit address following an `F000 NNNN` instruction.
+    fn long_operand(&self) -> Result<u16, Chip8Error> {
+        let high = self.read_memory(self.program_counter as usize + 2)?;
+        let low = self.read_memory(self.program_counter as usize + 3)?;
+        Ok((high as u16) << 8 | low as u16)
+    }
+
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
         }
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                Chip8::print_debug(&format!("BEEP"));
-            }
-            self.sound_timer -= 1;
+    }
+
+    fn skip_if(&mut self, condition: bool) -> Result<(), Chip8Error> {
+        if condition {
+            self.skip_next_instruction()
+        } else {
+            self.advance(2)
         }
     }
 
-    fn print_debug(msg: &String) {
-        let debug = true;
-        if debug {
-            println!("{}", msg);
+    /// Skips the instruction after the current one, which on XO-CHIP may be the four byte `F000 NNNN`.
+    fn skip_next_instruction(&mut self) -> Result<(), Chip8Error> {
+        let next = self.program_counter as usize + 2;
+        let long_load = self.variant == Variant::XoChip
+            && self.memory.get(next) == Some(&0xF0)
+            && self.memory.get(next + 1) == Some(&0x00);
+        self.advance(if long_load { 6 } else { 4 })
+    }
+
+    /// Moves the program counter on by `bytes`, failing instead of running off the end of the 16-bit
+    /// address space, where a ROM loaded at the top of XO-CHIP's memory would otherwise wrap to 0.
+    fn advance(&mut self, bytes: u16) -> Result<(), Chip8Error> {
+        let address = self.program_counter;
+        self.program_counter = address.checked_add(bytes)
+            .ok_or(Chip8Error::ProgramCounterOutOfRange { address })?;
+        Ok(())
+    }
+
+    /// The registers from VX to VY in `5XY2`/`5XY3`, in whichever direction.
+    fn register_range(x: u8, y: u8) -> impl Iterator<Item = usize> {
+        let (x, y) = (x as usize, y as usize);
+        let ascending = x <= y;
+        (0..=(x as isize - y as isize).unsigned_abs())
+            .map(move |step| if ascending { x + step } else { x - step })
+    }
+
+    /// Clears the selected bit-planes.
+    fn clear_screen(&mut self) {
+        let planes = self.planes;
+        for pixel in self.gfx.iter_mut() {
+            *pixel &= !planes;
         }
+        self.draw = true;
     }
+
+    /// Moves the selected bit-planes by (`dx`, `dy`) pixels, filling the uncovered area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.screen_width() as isize;
+        let height = self.screen_height() as isize;
+        let planes = self.planes;
+        let source = self.gfx;
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if from_x >= 0 && from_x < width && from_y >= 0 && from_y < height {
+                    source[(from_y * width + from_x) as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.gfx[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+        self.draw = true;
+    }
+
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An XO-CHIP machine with `program` loaded at `address`.
+    fn xochip_at(program: &[u8], address: u16) -> Chip8 {
+        let mut chip8 = with_seed(&[], 0);
+        chip8.set_variant(Variant::XoChip);
+        chip8.load_rom(program, address).unwrap();
+        chip8
+    }
+
+    /// A CHIP-8 machine with the given quirks and V0 to VF set to `registers`.
+    fn machine(quirks: Quirks, registers: &[u8]) -> Chip8 {
+        let mut chip8 = with_seed(&[], 0);
+        chip8.set_quirks(quirks);
+        for (register, &value) in registers.iter().enumerate() {
+            chip8.set_register(register, value);
+        }
+        chip8
+    }
+
+    /// Whether the low resolution pixel at (x, y) is lit.
+    fn lit(chip8: &Chip8, x: usize, y: usize) -> bool {
+        chip8.framebuffer()[y * SCREEN_WIDTH + x] != 0
+    }
+
+    #[test]
+    fn shift_uses_vx_or_vy() {
+        let mut chip8 = machine(Quirks::modern(), &[0b0000_0011, 0b1000_0000]);
+        chip8.execute(Instruction::ShiftRight { x: 0, y: 1 }).unwrap();
+        assert_eq!((chip8.registers()[0], chip8.registers()[0xF]), (0b0000_0001, 1));
+
+        let mut chip8 = machine(Quirks::cosmac_vip(), &[0b0000_0011, 0b1000_0000]);
+        chip8.execute(Instruction::ShiftRight { x: 0, y: 1 }).unwrap();
+        assert_eq!((chip8.registers()[0], chip8.registers()[0xF]), (0b0100_0000, 0));
+
+        let mut chip8 = machine(Quirks::cosmac_vip(), &[0, 0b1000_0001]);
+        chip8.execute(Instruction::ShiftLeft { x: 0, y: 1 }).unwrap();
+        assert_eq!((chip8.registers()[0], chip8.registers()[0xF]), (0b0000_0010, 1));
+    }
+
+    #[test]
+    fn jump_with_offset_uses_v0_or_vx() {
+        let mut chip8 = machine(Quirks::modern(), &[0x10, 0, 0x20]);
+        chip8.execute(Instruction::JumpOffset { nnn: 0x234 }).unwrap();
+        assert_eq!(chip8.program_counter(), 0x244);
+
+        let mut chip8 = machine(Quirks::superchip(), &[0x10, 0, 0x20]);
+        chip8.execute(Instruction::JumpOffset { nnn: 0x234 }).unwrap();
+        assert_eq!(chip8.program_counter(), 0x254);
+    }
+
+    #[test]
+    fn load_and_store_move_the_index_per_quirk() {
+        for &(quirks, index) in [
+            (Quirks::modern(), 0x300),
+            (Quirks::cosmac_vip(), 0x303),
+            (Quirks::chip48(), 0x302),
+        ].iter() {
+            let mut chip8 = machine(quirks, &[1, 2, 3]);
+            chip8.set_index(0x300);
+            chip8.execute(Instruction::Store { x: 2 }).unwrap();
+            assert_eq!(&chip8.memory()[0x300..0x303], &[1, 2, 3]);
+            assert_eq!(chip8.index(), index);
+
+            chip8.set_index(0x300);
+            chip8.execute(Instruction::Load { x: 2 }).unwrap();
+            assert_eq!(chip8.index(), index);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_per_quirk() {
+        let mut registers = [0; 16];
+        registers[0xF] = 7;
+        let mut chip8 = machine(Quirks::modern(), &registers);
+        chip8.execute(Instruction::Or { x: 0, y: 1 }).unwrap();
+        assert_eq!(chip8.registers()[0xF], 7);
+
+        let mut chip8 = machine(Quirks::cosmac_vip(), &registers);
+        chip8.execute(Instruction::Or { x: 0, y: 1 }).unwrap();
+        assert_eq!(chip8.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow() {
+        let mut chip8 = machine(Quirks::modern(), &[]);
+        assert_eq!(chip8.execute(Instruction::Return), Err(Chip8Error::StackUnderflow { address: PROGRAM_START }));
+        for _ in 0..16 {
+            chip8.execute(Instruction::Call { nnn: 0x300 }).unwrap();
+        }
+        assert_eq!(chip8.stack().len(), 16);
+        assert_eq!(chip8.execute(Instruction::Call { nnn: 0x300 }), Err(Chip8Error::StackOverflow { address: 0x300 }));
+        chip8.execute(Instruction::Return).unwrap();
+        assert_eq!(chip8.program_counter(), 0x302);
+    }
+
+    #[test]
+    fn sprites_clip_or_wrap_at_the_edges() {
+        // An 8x2 sprite drawn at (60, 31): half of it past the right edge, its second row past the bottom.
+        let draw = |quirks: Quirks| {
+            let mut chip8 = machine(quirks, &[60, 31]);
+            chip8.set_index(0x300);
+            chip8.set_memory(0x300, 0xFF);
+            chip8.set_memory(0x301, 0xFF);
+            chip8.execute(Instruction::Draw { x: 0, y: 1, n: 2 }).unwrap();
+            chip8
+        };
+
+        let clipped = draw(Quirks { wrap_sprites_x: false, wrap_sprites_y: false, ..Quirks::modern() });
+        assert!(lit(&clipped, 60, 31) && lit(&clipped, 63, 31));
+        assert!(!lit(&clipped, 0, 31) && !lit(&clipped, 60, 0));
+        assert_eq!(clipped.framebuffer().iter().filter(|&&pixel| pixel != 0).count(), 4);
+
+        let wrapped = draw(Quirks { wrap_sprites_x: true, wrap_sprites_y: true, ..Quirks::modern() });
+        assert!(lit(&wrapped, 63, 31) && lit(&wrapped, 3, 31));
+        assert!(lit(&wrapped, 60, 0) && lit(&wrapped, 3, 0));
+        assert_eq!(wrapped.framebuffer().iter().filter(|&&pixel| pixel != 0).count(), 16);
+    }
+
+    #[test]
+    fn sprite_start_position_always_wraps() {
+        let mut chip8 = machine(Quirks { wrap_sprites_x: false, wrap_sprites_y: false, ..Quirks::modern() }, &[65, 33]);
+        chip8.set_index(0x300);
+        chip8.set_memory(0x300, 0x80);
+        chip8.execute(Instruction::Draw { x: 0, y: 1, n: 1 }).unwrap();
+        assert!(lit(&chip8, 1, 1));
+    }
+
+    #[test]
+    fn drawing_over_a_pixel_sets_vf() {
+        let mut chip8 = machine(Quirks::modern(), &[0, 0]);
+        chip8.set_index(0x300);
+        chip8.set_memory(0x300, 0x80);
+        chip8.execute(Instruction::Draw { x: 0, y: 1, n: 1 }).unwrap();
+        assert_eq!(chip8.registers()[0xF], 0);
+        chip8.execute(Instruction::Draw { x: 0, y: 1, n: 1 }).unwrap();
+        assert_eq!(chip8.registers()[0xF], 1);
+        assert!(!lit(&chip8, 0, 0));
+    }
+
+    /// A machine that has drawn, used the timers and random numbers and called a subroutine.
+    fn busy_machine() -> Chip8 {
+        let program = [
+            0x60, 0x05, // V0 := 5
+            0xF0, 0x15, // delay := V0
+            0xC1, 0xFF, // V1 := random
+            0xA2, 0x10, // I := 0x210
+            0x22, 0x0C, // call 0x20C
+            0x12, 0x04, // jump 0x204, for another random number
+            0xD0, 0x15, // 0x20C: sprite V0 V1 5
+            0x00, 0xEE, // return
+            0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x00,
+        ];
+        let mut chip8 = with_seed(&[], 42);
+        chip8.load_rom(&program, PROGRAM_START).unwrap();
+        // Stop inside the subroutine, after drawing.
+        chip8.run_cycles(6).unwrap();
+        chip8.tick_timers();
+        chip8.set_key(0x7, true);
+        chip8
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut original = busy_machine();
+        let state = original.save_state();
+
+        let mut restored = with_seed(&[1, 2, 3], 7);
+        restored.set_variant(Variant::SuperChip);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.stack(), original.stack());
+        assert_eq!(restored.framebuffer(), original.framebuffer());
+
+        // Both carry on identically: the loop draws a new random number in V1 every six instructions.
+        let mut numbers = Vec::new();
+        for _ in 0..10 {
+            original.run_cycles(6).unwrap();
+            restored.run_cycles(6).unwrap();
+            assert_eq!(restored.registers()[1], original.registers()[1]);
+            numbers.push(original.registers()[1]);
+        }
+        assert_eq!(restored.save_state(), original.save_state());
+        numbers.dedup();
+        assert!(numbers.len() > 1, "CXNN never ran: {:?}", numbers);
+    }
+
+    #[test]
+    fn rejects_damaged_states() {
+        let state = busy_machine().save_state();
+        let mut chip8 = with_seed(&[], 0);
+        let untouched = chip8.save_state();
+
+        let mut wrong_magic = state.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(chip8.load_state(&wrong_magic), Err(StateError::NotAState));
+
+        let mut wrong_version = state.clone();
+        wrong_version[4..6].copy_from_slice(&(crate::state::STATE_VERSION + 1).to_be_bytes());
+        assert_eq!(chip8.load_state(&wrong_version), Err(StateError::UnsupportedVersion {
+            found: crate::state::STATE_VERSION + 1,
+            expected: crate::state::STATE_VERSION,
+        }));
+
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+        assert_eq!(chip8.load_state(&state[..3]), Err(StateError::NotAState));
+
+        let mut trailing = state.clone();
+        trailing.push(0);
+        assert_eq!(chip8.load_state(&trailing), Err(StateError::Invalid("length")));
+
+        assert_eq!(chip8.save_state(), untouched);
+    }
+
+    #[test]
+    fn program_counter_stops_at_the_end_of_memory() {
+        let mut chip8 = xochip_at(&[0x60, 0x00], 0xFFFE);
+        assert_eq!(chip8.emulate_cycle(), Err(Chip8Error::ProgramCounterOutOfRange { address: 0xFFFE }));
+    }
+
+    #[test]
+    fn skip_stops_at_the_end_of_memory() {
+        let mut chip8 = xochip_at(&[0x30, 0x00], 0xFFFC);
+        assert_eq!(chip8.emulate_cycle(), Err(Chip8Error::ProgramCounterOutOfRange { address: 0xFFFC }));
+    }
+
+    #[test]
+    fn keys_use_the_low_nibble() {
+        let mut chip8 = with_seed(&[0xF3, 0x0A], 0); // V3 := key
+        chip8.emulate_cycle().unwrap();
+        chip8.set_key(0x1A, true);
+        assert!(chip8.is_key_pressed(0xA));
+        assert!(chip8.is_key_pressed(0xFA));
+        chip8.set_key(0xFA, false);
+        assert!(!chip8.is_key_pressed(0xA));
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.registers()[3], 0xA);
+    }
+}
@@ -0,0 +1,608 @@
+//! Command-line parsing: the `run`, `disasm`, `headless` and `info` subcommands and their help texts.
+
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chip8_rust::audio::{Tone, Waveform};
+use chip8_rust::chip8::{self, Variant, TIMER_FREQUENCY};
+use chip8_rust::disasm::Syntax;
+use chip8_rust::quirks::Quirks;
+use chip8_rust::snapshot::{self, ImageFormat, Palette, DEFAULT_PALETTE};
+use chip8_rust::trace::{TraceFilter, TraceFormat};
+
+/// How far back Backspace can rewind unless `--rewind-seconds` says otherwise.
+pub const DEFAULT_REWIND_SECONDS: u32 = 30;
+/// The most `--rewind-seconds` accepts. Every frame in between is kept in memory.
+pub const MAX_REWIND_SECONDS: u32 = 600;
+/// Size of a CHIP-8 pixel in window pixels unless `--scale` says otherwise.
+pub const DEFAULT_SCALE: u32 = 16;
+/// Number of frames `headless` runs unless `--frames` or `--cycles` says otherwise.
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+
+const HELP: &str = "\
+A CHIP-8, SUPER-CHIP and XO-CHIP interpreter.
+
+Usage: chip8-rust [run] [OPTIONS] ROM-FILENAME
+       chip8-rust disasm [OPTIONS] ROM-FILENAME
+       chip8-rust headless [OPTIONS] ROM-FILENAME
+       chip8-rust info [OPTIONS] ROM-FILENAME
+       chip8-rust help [COMMAND]
+
+Commands:
+  run       Play a ROM in a window (the default)
+  disasm    Print an annotated listing of a ROM
+  headless  Run a ROM without a window and dump the display and registers
+  info      Print what the interpreter can tell about a ROM without running it
+
+Run `chip8-rust help COMMAND` for the options of a command.
+Exits with status 1 when something fails and 2 when the command line is invalid.";
+
+const MACHINE_OPTIONS: &str = "  --variant chip8|schip|xochip  Instruction set to interpret (default chip8)
+  --quirks vip|chip48|schip|xochip|modern
+                                Which interpreter's behaviour to emulate (default modern)
+  --cycles-per-frame N          Instructions executed per 60 Hz frame (default 10)
+  --clock HZ                    Instructions executed per second, rounded to whole frames
+  --seed N                      Seed for CXNN's random numbers, for reproducible runs
+  --load-address ADDR           Hexadecimal address to load the ROM and start at (default 200)
+  --no-database                 Ignore the ROM settings database, which picks the variant, quirks,
+                                speed, colours and keys of known ROMs unless they are given here";
+
+const RUN_HELP: &str = "\
+Play a ROM in a window.
+
+Usage: chip8-rust [run] [OPTIONS] ROM-FILENAME
+
+Machine:
+{machine}
+
+Configuration:
+  --config FILE                 Key mappings and other settings (default {config})
+                                ROMs missing from the settings database can be added to {database}
+
+Display and sound:
+  --scale N                     Size of a CHIP-8 pixel in window pixels (default 16)
+  --fullscreen                  Start in fullscreen
+  --colors RGB,RGB[,RGB,RGB]    Hexadecimal background and foreground colours, plus XO-CHIP's
+                                other two (default 000000,FFFFFF,FF6600,662100)
+  --tone-frequency HZ           Pitch of the beep (default 440)
+  --volume 0-1                  Volume of the beep
+  --waveform square|triangle|sawtooth|sine
+                                Shape of the beep
+
+Debugging:
+  --debug                       Start paused with a debugger prompt in the terminal
+  --trace FILE                  Write every executed instruction to FILE
+  --trace-format text|json      Format of the trace (default text)
+  --trace-opcodes D,F           Only trace these opcode classes (first hex digit)
+  --trace-range 200-2FF         Only trace instructions in this address range
+  --rewind-seconds N            How far back Backspace rewinds (default 30, at most 600, 0 turns it off)
+
+Recording:
+  --record FILE                 Write every key press to a movie file on exit; pausing, stepping and
+                                loading states are disabled while recording
+  --replay FILE                 Play back a movie, ignoring the keyboard until it ends";
+
+const DISASM_HELP: &str = "\
+Print an annotated listing of a ROM.
+
+Usage: chip8-rust disasm [OPTIONS] ROM-FILENAME
+
+  --syntax octo|classic         Mnemonics to use (default octo)
+  --load-address ADDR           Hexadecimal address the ROM is loaded at (default 200)";
+
+const HEADLESS_HELP: &str = "\
+Run a ROM without a window, then dump the display and registers.
+
+Usage: chip8-rust headless [OPTIONS] ROM-FILENAME
+
+Machine:
+{machine}
+
+Running:
+  --frames N                    Number of 60 Hz frames to run (default 600)
+  --cycles N                    Number of instructions to run instead of a number of frames; they
+                                still run in frames, so the timers tick and --replay applies
+  --replay FILE                 Feed the input of a recorded movie, using its configuration
+
+Output:
+  --screenshot FILE             Write the display to FILE (ASCII art on stdout by default)
+  --format png|pbm|ascii        Image format (default from the extension of FILE)
+  --scale N                     Size of a CHIP-8 pixel in PNG and PBM images (default 1)
+  --colors RGB,RGB[,RGB,RGB]    Hexadecimal colours of PNG images
+  --registers FILE              Write the registers as JSON to FILE, or to stdout with -
+
+Exits with status 1 if the ROM crashes.";
+
+const INFO_HELP: &str = "\
+Print the size and SHA-1 hash of a ROM, the instruction set it needs, what the settings database
+knows about it and anything suspicious about it.
+
+Usage: chip8-rust info [OPTIONS] ROM-FILENAME
+
+  --variant chip8|schip|xochip  Instruction set to check the ROM against (default xochip)
+  --load-address ADDR           Hexadecimal address the ROM is loaded at (default 200)";
+
+/// What the command line asks for.
+pub enum Command {
+    Run(RunOptions),
+    Disasm(DisasmOptions),
+    Headless(HeadlessOptions),
+    Info(InfoOptions),
+    /// Print this help text and exit successfully.
+    Help(String),
+}
+
+/// Settings shared by every command that creates a machine. The ones left out on the command
+/// line are `None` and come from the ROM settings database, or else the defaults.
+pub struct MachineOptions {
+    pub variant: Option<Variant>,
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub seed: Option<u64>,
+    pub load_address: u16,
+    /// Cleared by `--no-database` to ignore the ROM settings database.
+    pub use_database: bool,
+}
+
+impl Default for MachineOptions {
+    fn default() -> MachineOptions {
+        MachineOptions {
+            variant: None,
+            quirks: None,
+            cycles_per_frame: None,
+            seed: None,
+            load_address: chip8::PROGRAM_START,
+            use_database: true,
+        }
+    }
+}
+
+/// The options of `run`, which only the window frontend reads.
+#[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+pub struct RunOptions {
+    pub rom_path: String,
+    pub machine: MachineOptions,
+    /// Set by `--config`; otherwise [`default_config_path`] is read if it exists.
+    pub config_path: Option<String>,
+    pub scale: u32,
+    pub fullscreen: bool,
+    /// Set by `--colors`; otherwise from the settings database or the default.
+    pub palette: Option<Palette>,
+    pub tone: Tone,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub debug: bool,
+    pub rewind_seconds: u32,
+    pub record_path: Option<String>,
+    pub replay_path: Option<String>,
+}
+
+pub struct DisasmOptions {
+    pub rom_path: String,
+    pub syntax: Syntax,
+    pub load_address: u16,
+}
+
+pub struct HeadlessOptions {
+    pub rom_path: String,
+    pub machine: MachineOptions,
+    pub frames: u64,
+    pub cycles: Option<usize>,
+    pub replay_path: Option<String>,
+    pub screenshot_path: Option<String>,
+    pub format: Option<ImageFormat>,
+    pub scale: usize,
+    pub palette: Option<Palette>,
+    pub registers_path: Option<String>,
+}
+
+pub struct InfoOptions {
+    pub rom_path: String,
+    pub variant: Variant,
+    pub load_address: u16,
+}
+
+/// Parses the arguments after the program name. Errors are meant to be printed followed by
+/// a hint to run `--help`.
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let first = match args.next() {
+        Some(first) => first,
+        None => return parse_run(std::iter::empty()),
+    };
+    match first.as_str() {
+        "-h" | "--help" => Ok(Command::Help(String::from(HELP))),
+        "help" => match args.next() {
+            Some(command) => help(&command).map(Command::Help).ok_or_else(|| format!("Unknown command \"{}\"", command)),
+            None => Ok(Command::Help(String::from(HELP))),
+        },
+        "run" => parse_run(args),
+        "disasm" => parse_disasm(args),
+        "headless" => parse_headless(args),
+        "info" => parse_info(args),
+        // Without a command the arguments are for `run`, as before there were commands.
+        _ => parse_run(std::iter::once(first).chain(args)),
+    }
+}
+
+fn run_help() -> String {
+    let config = default_config_path().map_or_else(|| String::from("none"), |path| path.display().to_string());
+    let database = user_database_path().map_or_else(|| String::from("none"), |path| path.display().to_string());
+    RUN_HELP.replace("{machine}", MACHINE_OPTIONS).replace("{config}", &config).replace("{database}", &database)
+}
+
+/// `chip8-rust` in the user's configuration directory.
+fn config_directory() -> Option<PathBuf> {
+    let directory = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(directory.join("chip8-rust"))
+}
+
+/// Where the configuration file is read from without `--config`: `chip8-rust/config.toml` in the
+/// user's configuration directory.
+pub fn default_config_path() -> Option<PathBuf> {
+    config_directory().map(|directory| directory.join("config.toml"))
+}
+
+/// The user's additions to the ROM settings database, `chip8-rust/roms.toml` in the configuration directory.
+pub fn user_database_path() -> Option<PathBuf> {
+    config_directory().map(|directory| directory.join("roms.toml"))
+}
+
+fn help(command: &str) -> Option<String> {
+    match command {
+        "run" => Some(run_help()),
+        "disasm" => Some(String::from(DISASM_HELP)),
+        "headless" => Some(HEADLESS_HELP.replace("{machine}", MACHINE_OPTIONS)),
+        "info" => Some(String::from(INFO_HELP)),
+        _ => None
+    }
+}
+
+fn option_value<T: FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T, String> {
+    match args.next().map(|value| value.parse()) {
+        Some(Ok(value)) => Ok(value),
+        _ => Err(format!("{} expects a value", name)),
+    }
+}
+
+/// Reads a hexadecimal address such as `600` or `0x600`.
+fn address_value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<u16, String> {
+    let value: String = option_value(args, name)?;
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("{} expects a hexadecimal address", name))
+}
+
+fn variant_value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<Variant, String> {
+    let value: String = option_value(args, name)?;
+    Variant::from_name(&value).ok_or_else(|| format!("Unknown variant \"{}\"", value))
+}
+
+/// Reads two to four comma-separated hexadecimal colours; the ones left out keep their defaults.
+fn palette_value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<Palette, String> {
+    let value: String = option_value(args, name)?;
+    let colors: Vec<&str> = value.split(',').collect();
+    if colors.len() < 2 || colors.len() > 4 {
+        return Err(format!("{} expects two to four colours", name));
+    }
+    let mut palette = DEFAULT_PALETTE;
+    for (entry, color) in palette.iter_mut().zip(colors) {
+        *entry = snapshot::parse_color(color).ok_or_else(|| format!("Invalid colour \"{}\"", color))?;
+    }
+    Ok(palette)
+}
+
+fn rom_path(rom_path: Option<String>, command: &str) -> Result<String, String> {
+    rom_path.ok_or_else(|| format!("`chip8-rust {}` needs a ROM-FILENAME", command))
+}
+
+/// Handles `arg` if it is one of the [`MachineOptions`], returning whether it was.
+fn parse_machine_option(
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+    machine: &mut MachineOptions,
+) -> Result<bool, String> {
+    match arg {
+        "--variant" => machine.variant = Some(variant_value(args, arg)?),
+        "--quirks" => {
+            let name: String = option_value(args, arg)?;
+            machine.quirks = Some(Quirks::from_name(&name)
+                .ok_or_else(|| format!("Unknown quirk profile \"{}\"", name))?);
+        },
+        "--cycles-per-frame" => {
+            let cycles_per_frame = option_value(args, arg)?;
+            if cycles_per_frame == 0 {
+                return Err(String::from("--cycles-per-frame expects a positive number"));
+            }
+            machine.cycles_per_frame = Some(cycles_per_frame);
+        },
+        "--clock" => {
+            let hz: u32 = option_value(args, arg)?;
+            if hz == 0 {
+                return Err(String::from("--clock expects a positive number"));
+            }
+            machine.cycles_per_frame = Some(((hz + TIMER_FREQUENCY / 2) / TIMER_FREQUENCY).max(1));
+        },
+        "--no-database" => machine.use_database = false,
+        "--seed" => machine.seed = Some(option_value(args, arg)?),
+        "--load-address" => machine.load_address = address_value(args, arg)?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Takes `arg` as the ROM. Fails on anything that looks like an option, so typos aren't taken for the ROM,
+/// and on a second ROM.
+fn positional(arg: String, rom_path: &mut Option<String>) -> Result<(), String> {
+    if arg.starts_with("--") {
+        return Err(format!("Unknown option \"{}\"", arg));
+    }
+    if let Some(first) = rom_path {
+        return Err(format!("Unexpected argument \"{}\", the ROM is already \"{}\"", arg, first));
+    }
+    *rom_path = Some(arg);
+    Ok(())
+}
+
+fn parse_run(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut machine = MachineOptions::default();
+    let mut config_path = None;
+    let mut scale = DEFAULT_SCALE;
+    let mut fullscreen = false;
+    let mut palette = None;
+    let mut tone = Tone::default();
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::all();
+    let mut debug = false;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut record_path = None;
+    let mut replay_path = None;
+    while let Some(arg) = args.next() {
+        if parse_machine_option(&arg, &mut args, &mut machine)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(run_help())),
+            "--config" => config_path = Some(option_value(&mut args, &arg)?),
+            "--scale" => {
+                scale = option_value(&mut args, &arg)?;
+                if scale == 0 {
+                    return Err(String::from("--scale expects a positive number"));
+                }
+            },
+            "--fullscreen" => fullscreen = true,
+            "--colors" => palette = Some(palette_value(&mut args, &arg)?),
+            "--tone-frequency" => tone.frequency = option_value(&mut args, &arg)?,
+            "--volume" => {
+                tone.volume = option_value(&mut args, &arg)?;
+                if !(0.0..=1.0).contains(&tone.volume) {
+                    return Err(String::from("--volume expects a number from 0 to 1"));
+                }
+            },
+            "--waveform" => {
+                let name: String = option_value(&mut args, &arg)?;
+                tone.waveform = Waveform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown waveform \"{}\"", name))?;
+            },
+            "--trace" => trace_path = Some(option_value(&mut args, &arg)?),
+            "--trace-format" => {
+                let name: String = option_value(&mut args, &arg)?;
+                trace_format = TraceFormat::from_name(&name)
+                    .ok_or_else(|| format!("Unknown trace format \"{}\"", name))?;
+            },
+            "--trace-opcodes" => {
+                let classes: String = option_value(&mut args, &arg)?;
+                trace_filter.opcode_classes = TraceFilter::parse_opcode_classes(&classes)
+                    .ok_or_else(|| format!("Invalid opcode classes \"{}\"", classes))?;
+            },
+            "--trace-range" => {
+                let range: String = option_value(&mut args, &arg)?;
+                trace_filter.address_range = Some(TraceFilter::parse_address_range(&range)
+                    .ok_or_else(|| format!("Invalid address range \"{}\"", range))?);
+            },
+            "--debug" => debug = true,
+            "--rewind-seconds" => {
+                rewind_seconds = option_value(&mut args, &arg)?;
+                if rewind_seconds > MAX_REWIND_SECONDS {
+                    return Err(format!("--rewind-seconds expects at most {}", MAX_REWIND_SECONDS));
+                }
+            },
+            "--record" => record_path = Some(option_value(&mut args, &arg)?),
+            "--replay" => replay_path = Some(option_value(&mut args, &arg)?),
+            _ => positional(arg, &mut rom_path)?,
+        }
+    }
+    if record_path.is_some() && replay_path.is_some() {
+        return Err(String::from("--record and --replay can't be combined"));
+    }
+    if record_path.is_some() && debug {
+        // Breakpoints and stepping stop in the middle of frames, which a movie can't reproduce.
+        return Err(String::from("--record and --debug can't be combined"));
+    }
+    Ok(Command::Run(RunOptions {
+        rom_path: self::rom_path(rom_path, "run")?,
+        machine,
+        config_path,
+        scale,
+        fullscreen,
+        palette,
+        tone,
+        trace_path,
+        trace_format,
+        trace_filter,
+        debug,
+        rewind_seconds,
+        record_path,
+        replay_path,
+    }))
+}
+
+fn parse_disasm(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut syntax = Syntax::Octo;
+    let mut load_address = chip8::PROGRAM_START;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(String::from(DISASM_HELP))),
+            "--syntax" => {
+                let name: String = option_value(&mut args, &arg)?;
+                syntax = Syntax::from_name(&name).ok_or_else(|| format!("Unknown syntax \"{}\"", name))?;
+            },
+            "--load-address" => load_address = address_value(&mut args, &arg)?,
+            _ => positional(arg, &mut rom_path)?,
+        }
+    }
+    Ok(Command::Disasm(DisasmOptions { rom_path: self::rom_path(rom_path, "disasm")?, syntax, load_address }))
+}
+
+fn parse_headless(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut machine = MachineOptions::default();
+    let mut frames = DEFAULT_HEADLESS_FRAMES;
+    let mut cycles = None;
+    let mut replay_path = None;
+    let mut screenshot_path = None;
+    let mut format = None;
+    let mut scale = 1;
+    let mut palette = None;
+    let mut registers_path = None;
+    while let Some(arg) = args.next() {
+        if parse_machine_option(&arg, &mut args, &mut machine)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(HEADLESS_HELP.replace("{machine}", MACHINE_OPTIONS))),
+            "--frames" => frames = option_value(&mut args, &arg)?,
+            "--cycles" => cycles = Some(option_value(&mut args, &arg)?),
+            "--replay" => replay_path = Some(option_value(&mut args, &arg)?),
+            "--screenshot" => screenshot_path = Some(option_value(&mut args, &arg)?),
+            "--format" => {
+                let name: String = option_value(&mut args, &arg)?;
+                format = Some(ImageFormat::from_name(&name).ok_or_else(|| format!("Unknown image format \"{}\"", name))?);
+            },
+            "--scale" => {
+                scale = option_value(&mut args, &arg)?;
+                if scale == 0 {
+                    return Err(String::from("--scale expects a positive number"));
+                }
+            },
+            "--colors" => palette = Some(palette_value(&mut args, &arg)?),
+            "--registers" => registers_path = Some(option_value(&mut args, &arg)?),
+            _ => positional(arg, &mut rom_path)?,
+        }
+    }
+    Ok(Command::Headless(HeadlessOptions {
+        rom_path: self::rom_path(rom_path, "headless")?,
+        machine,
+        frames,
+        cycles,
+        replay_path,
+        screenshot_path,
+        format,
+        scale,
+        palette,
+        registers_path,
+    }))
+}
+
+fn parse_info(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut variant = Variant::XoChip;
+    let mut load_address = chip8::PROGRAM_START;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(String::from(INFO_HELP))),
+            "--variant" => variant = variant_value(&mut args, &arg)?,
+            "--load-address" => load_address = address_value(&mut args, &arg)?,
+            _ => positional(arg, &mut rom_path)?,
+        }
+    }
+    Ok(Command::Info(InfoOptions { rom_path: self::rom_path(rom_path, "info")?, variant, load_address }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run_options(args: &[&str]) -> RunOptions {
+        match parse_args(args) {
+            Ok(Command::Run(options)) => options,
+            Err(e) => panic!("{}", e),
+            _ => panic!("not a run command"),
+        }
+    }
+
+    fn headless_options(args: &[&str]) -> HeadlessOptions {
+        match parse_args(args) {
+            Ok(Command::Headless(options)) => options,
+            Err(e) => panic!("{}", e),
+            _ => panic!("not a headless command"),
+        }
+    }
+
+    #[test]
+    fn runs_without_a_command() {
+        let options = run_options(&["--scale", "4", "pong.ch8", "--volume", "0.5"]);
+        assert_eq!(options.rom_path, "pong.ch8");
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.tone.volume, 0.5);
+        assert_eq!(options.rewind_seconds, DEFAULT_REWIND_SECONDS);
+        assert_eq!(run_options(&["run", "pong.ch8"]).rom_path, "pong.ch8");
+    }
+
+    #[test]
+    fn reads_machine_options() {
+        let options = headless_options(&["headless", "--variant", "schip", "--clock", "700", "--load-address", "0x600", "pong.ch8"]);
+        assert_eq!(options.machine.variant, Some(Variant::SuperChip));
+        assert_eq!(options.machine.cycles_per_frame, Some(12));
+        assert_eq!(options.machine.load_address, 0x600);
+        assert_eq!(options.scale, 1);
+    }
+
+    #[test]
+    fn checks_ranges() {
+        assert!(parse_args(&["--volume", "1.5", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--volume", "-0.1", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--volume", "NaN", "pong.ch8"]).is_err());
+        assert_eq!(run_options(&["--volume", "0", "pong.ch8"]).tone.volume, 0.0);
+        assert_eq!(run_options(&["--volume", "1", "pong.ch8"]).tone.volume, 1.0);
+
+        assert_eq!(run_options(&["--rewind-seconds", "0", "pong.ch8"]).rewind_seconds, 0);
+        assert_eq!(run_options(&["--rewind-seconds", "600", "pong.ch8"]).rewind_seconds, MAX_REWIND_SECONDS);
+        assert!(parse_args(&["--rewind-seconds", "601", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--rewind-seconds", "4294967295", "pong.ch8"]).is_err());
+
+        assert!(parse_args(&["--scale", "0", "pong.ch8"]).is_err());
+        assert!(parse_args(&["headless", "--scale", "0", "pong.ch8"]).is_err());
+        assert_eq!(headless_options(&["headless", "--scale", "3", "pong.ch8"]).scale, 3);
+        assert!(parse_args(&["--cycles-per-frame", "0", "pong.ch8"]).is_err());
+        assert!(parse_args(&["--clock", "0", "pong.ch8"]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_second_rom() {
+        for command in &["run", "disasm", "headless", "info"] {
+            let error = parse_args(&[command, "pong.ch8", "tetris.ch8"]).err();
+            assert_eq!(error.as_deref(), Some("Unexpected argument \"tetris.ch8\", the ROM is already \"pong.ch8\""));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(parse_args(&["--sacle", "4", "pong.ch8"]).err().as_deref(), Some("Unknown option \"--sacle\""));
+        assert_eq!(parse_args(&["--scale"]).err().as_deref(), Some("--scale expects a value"));
+        assert_eq!(parse_args(&["--scale", "big", "pong.ch8"]).err().as_deref(), Some("--scale expects a value"));
+        assert_eq!(parse_args(&["disasm"]).err().as_deref(), Some("`chip8-rust disasm` needs a ROM-FILENAME"));
+        assert!(parse_args(&["--record", "a.movie", "--replay", "b.movie", "pong.ch8"]).is_err());
+        assert!(parse_args(&["help", "nonsense"]).is_err());
+        assert!(matches!(parse_args(&["help", "headless"]), Ok(Command::Help(_))));
+    }
+}
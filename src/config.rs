@@ -0,0 +1,170 @@
+//! Reading the TOML configuration files, parsed with the `toml` crate.
+//!
+//! Nested tables, whether written as `[table]` headers, dotted keys or inline tables, are
+//! flattened into one [`Table`] per path, so callers can look up e.g. `[rom."pong.ch8".keys]`
+//! directly.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+pub use toml::Value;
+
+/// The key-value pairs of one table, without its nested tables.
+pub type Table = BTreeMap<String, Value>;
+
+/// The value as a list of strings: a single string, or an array of only strings.
+pub fn strings(value: &Value) -> Option<Vec<&str>> {
+    match value {
+        Value::String(string) => Some(vec![string.as_str()]),
+        Value::Array(values) => values.iter().map(Value::as_str).collect(),
+        _ => None
+    }
+}
+
+/// A parsed file: its tables keyed by their path, the top level being the empty path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    tables: BTreeMap<Vec<String>, Table>,
+}
+
+/// A syntax error in a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let root: toml::Table = text.parse().map_err(|e: toml::de::Error| {
+            let offset = e.span().map_or(0, |span| span.start);
+            // Messages can span lines, e.g. "invalid array\nexpected `]`".
+            let message = e.message().trim_end().replace('\n', ": ");
+            ConfigError { line: text[..offset].matches('\n').count() + 1, message }
+        })?;
+        let mut config = Config::default();
+        config.insert(Vec::new(), root);
+        Ok(config)
+    }
+
+    /// Adds `table` at `path` and its nested tables below it.
+    fn insert(&mut self, path: Vec<String>, table: toml::Table) {
+        let mut values = Table::new();
+        for (name, value) in table {
+            match value {
+                Value::Table(nested) => {
+                    let mut nested_path = path.clone();
+                    nested_path.push(name);
+                    self.insert(nested_path, nested);
+                },
+                value => {
+                    values.insert(name, value);
+                },
+            }
+        }
+        self.tables.insert(path, values);
+    }
+
+    /// The table at `path`, e.g. `&["keys"]` for `[keys]` or `&[]` for the top level.
+    pub fn table(&self, path: &[&str]) -> Option<&Table> {
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        self.tables.get(&path)
+    }
+
+    /// Every table with its path, in sorted order.
+    pub fn tables(&self) -> impl Iterator<Item = (&[String], &Table)> {
+        self.tables.iter().map(|(path, table)| (path.as_slice(), table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> Value {
+        Value::String(String::from(value))
+    }
+
+    #[test]
+    fn reads_comments_keys_and_values() {
+        let config = Config::parse(
+            "# settings\n\
+             speed = 700 # instructions per second\n\
+             \n\
+             [keys]\n\
+             1 = \"X\"\n\
+             \"with space\" = 'literal \\n'\n\
+             enabled = true\n\
+             offset = -16\n\
+             big = 1_000\n\
+             mask = 0xF0\n",
+        )
+        .unwrap();
+        assert_eq!(config.table(&[]).unwrap()["speed"], Value::Integer(700));
+        let keys = config.table(&["keys"]).unwrap();
+        assert_eq!(keys["1"], string("X"));
+        assert_eq!(keys["with space"], string("literal \\n"));
+        assert_eq!(keys["enabled"], Value::Boolean(true));
+        assert_eq!(keys["offset"], Value::Integer(-16));
+        assert_eq!(keys["big"], Value::Integer(1000));
+        assert_eq!(keys["mask"], Value::Integer(0xF0));
+    }
+
+    #[test]
+    fn flattens_nested_tables() {
+        let config = Config::parse("[rom.\"pong.ch8\"]\nspeed = 1\n[ rom . 'a b' ]\nspeed = 2\n").unwrap();
+        assert_eq!(config.table(&["rom", "pong.ch8"]).unwrap()["speed"], Value::Integer(1));
+        assert_eq!(config.table(&["rom", "a b"]).unwrap()["speed"], Value::Integer(2));
+        let paths: Vec<&[String]> = config.tables().map(|(path, _)| path).collect();
+        assert_eq!(paths.len(), 4);
+        assert!(config.table(&["rom"]).unwrap().is_empty());
+
+        let config = Config::parse("gamepad.dead-zone = 30\n[rom]\n\"pong.ch8\".keys = { 1 = \"Q\" }\n").unwrap();
+        assert_eq!(config.table(&["gamepad"]).unwrap()["dead-zone"], Value::Integer(30));
+        assert_eq!(config.table(&["rom", "pong.ch8", "keys"]).unwrap()["1"], string("Q"));
+    }
+
+    #[test]
+    fn reads_arrays_over_several_lines() {
+        let config = Config::parse("keys = [\n  \"A\", # first\n\n  'B',\n  [1, 2],\n]\nnext = 1\n").unwrap();
+        let table = config.table(&[]).unwrap();
+        assert_eq!(
+            table["keys"],
+            Value::Array(vec![string("A"), string("B"), Value::Array(vec![Value::Integer(1), Value::Integer(2)])])
+        );
+        assert_eq!(table["next"], Value::Integer(1));
+        assert_eq!(strings(&Value::Array(vec![string("A"), string("B")])), Some(vec!["A", "B"]));
+        assert_eq!(strings(&string("A")), Some(vec!["A"]));
+        assert_eq!(strings(&table["keys"]), None);
+    }
+
+    #[test]
+    fn reads_escapes_in_basic_strings() {
+        let config = Config::parse(r#"text = "tab\tquote\" slash\\ line\n \u00e9""#).unwrap();
+        assert_eq!(config.table(&[]).unwrap()["text"], string("tab\tquote\" slash\\ line\n é"));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = |text: &str| Config::parse(text).unwrap_err();
+        assert_eq!(error("a = 1\n\nb = 1.5.2\n").line, 3);
+        assert_eq!(error("a = 1\nb = \"open\nc = 2\n").line, 2);
+        assert_eq!(error("a = [\n1,\n2\n3]\n").line, 4);
+        assert_eq!(error("# comment\n[t]\n[t]\n").line, 3);
+        assert_eq!(error("a = 1\na = 2\n").line, 2);
+        assert_eq!(error("\n\na = \"\\q\"\n").line, 3);
+        assert_eq!(error("a = yes\n").line, 1);
+        assert!(error("a = 1\na = 2\n").to_string().starts_with("line 2: "));
+        assert_eq!(error("a = [\"A\"\nb = 1\n").message, "invalid array: expected `]`");
+    }
+}
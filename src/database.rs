@@ -0,0 +1,183 @@
+//! Settings for known ROMs, looked up by the SHA-1 hash of the ROM.
+//!
+//! Different programs were written for different interpreters, so many only run
+//! right with a particular variant, set of quirks or speed. The database records
+//! those, like the community chip-8-database does, in the configuration file format:
+//!
+//! ```toml
+//! [0123456789abcdef0123456789abcdef01234567]
+//! title = "Some Game"
+//! variant = "schip"
+//! quirks = "schip"
+//! cycles-per-frame = 30
+//! colors = ["000000", "FFCC00"]
+//!
+//! [0123456789abcdef0123456789abcdef01234567.keys]
+//! 5 = ["Up"]
+//! ```
+//!
+//! The database bundled with the interpreter can be extended or overridden by a
+//! file of the same format, see [`Database::merge`].
+
+use std::collections::BTreeMap;
+
+use crate::chip8::Variant;
+use crate::config::{self, Config, Table};
+use crate::quirks::Quirks;
+use crate::rom::sha1_hex;
+use crate::snapshot::{parse_color, Palette, DEFAULT_PALETTE};
+
+/// The database shipped with the interpreter.
+const BUNDLED: &str = include_str!("roms.toml");
+
+/// What the database knows about one ROM. Settings it doesn't mention are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomSettings {
+    pub title: Option<String>,
+    pub variant: Option<Variant>,
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub palette: Option<Palette>,
+    /// A key mapping table in the format of the configuration file's `[keys]`.
+    pub keys: Option<Table>,
+}
+
+impl RomSettings {
+    fn from_table(table: &Table) -> Result<RomSettings, String> {
+        let mut settings = RomSettings::default();
+        for (name, value) in table {
+            let invalid = || format!("invalid {} {:?}", name, value);
+            match name.as_str() {
+                "title" => settings.title = Some(value.as_str().ok_or_else(invalid)?.to_string()),
+                "variant" => settings.variant = Some(value.as_str().and_then(Variant::from_name).ok_or_else(invalid)?),
+                "quirks" => settings.quirks = Some(value.as_str().and_then(Quirks::from_name).ok_or_else(invalid)?),
+                "cycles-per-frame" => {
+                    let cycles = value.as_integer().filter(|&cycles| cycles > 0 && cycles <= u32::MAX as i64);
+                    settings.cycles_per_frame = Some(cycles.ok_or_else(invalid)? as u32);
+                },
+                "colors" => {
+                    let colors = config::strings(value).filter(|colors| (2..=4).contains(&colors.len())).ok_or_else(invalid)?;
+                    let mut palette = DEFAULT_PALETTE;
+                    for (entry, color) in palette.iter_mut().zip(colors) {
+                        *entry = parse_color(color).ok_or_else(invalid)?;
+                    }
+                    settings.palette = Some(palette);
+                },
+                _ => return Err(format!("unknown setting \"{}\"", name)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Database {
+    /// Keyed by lowercase SHA-1 hash.
+    entries: BTreeMap<String, RomSettings>,
+}
+
+impl Database {
+    /// The database shipped with the interpreter.
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED).expect("the bundled database is valid")
+    }
+
+    /// Parses a database file, in which every top-level table is named after a ROM's SHA-1 hash.
+    pub fn parse(text: &str) -> Result<Database, String> {
+        let config = Config::parse(text).map_err(|e| e.to_string())?;
+        let mut database = Database::default();
+        for (path, table) in config.tables() {
+            match path {
+                [] if table.is_empty() => {},
+                [] => return Err(String::from("settings must be in a table named after the ROM's SHA-1 hash")),
+                [hash] => {
+                    if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(format!("[{}]: not a SHA-1 hash", hash));
+                    }
+                    let settings = RomSettings::from_table(table).map_err(|e| format!("[{}]: {}", hash, e))?;
+                    let entry = database.entries.entry(hash.to_ascii_lowercase()).or_default();
+                    let keys = entry.keys.take();
+                    *entry = RomSettings { keys, ..settings };
+                },
+                [hash, keys] if keys == "keys" => {
+                    database.entries.entry(hash.to_ascii_lowercase()).or_default().keys = Some(table.clone());
+                },
+                _ => return Err(format!("[{}]: unknown table", path.join("."))),
+            }
+        }
+        Ok(database)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds the entries of `other`, replacing this database's entries for the same ROMs.
+    pub fn merge(&mut self, other: Database) {
+        self.entries.extend(other.entries);
+    }
+
+    /// The settings for the ROM whose SHA-1 hash is `hash`.
+    pub fn get(&self, hash: &str) -> Option<&RomSettings> {
+        self.entries.get(&hash.to_ascii_lowercase())
+    }
+
+    /// The settings for `rom_bytes`, if the database knows the ROM.
+    pub fn lookup(&self, rom_bytes: &[u8]) -> Option<&RomSettings> {
+        self.get(&sha1_hex(rom_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn bundled_database_parses() {
+        Database::bundled();
+    }
+
+    #[test]
+    fn reads_settings_and_keys() {
+        let database = Database::parse(&format!(
+            "[{hash}.keys]\n5 = [\"Up\"]\n\n[{hash}]\ntitle = \"Some Game\"\nvariant = \"schip\"\n\
+             cycles-per-frame = 30\ncolors = [\"000000\", \"FFCC00\"]\n",
+            hash = HASH.to_ascii_uppercase()
+        ))
+        .unwrap();
+        let settings = database.get(HASH).unwrap();
+        assert_eq!(settings.title.as_deref(), Some("Some Game"));
+        assert_eq!(settings.variant, Some(Variant::SuperChip));
+        assert_eq!(settings.cycles_per_frame, Some(30));
+        assert_eq!(settings.palette.unwrap()[1], [0xFF, 0xCC, 0x00]);
+        assert!(settings.keys.as_ref().unwrap().contains_key("5"));
+
+        // Looked up by the hash of the ROM's bytes, here an empty one.
+        let database = Database::parse("[da39a3ee5e6b4b0d3255bfef95601890afd80709]\ntitle = \"Empty\"\n").unwrap();
+        assert_eq!(database.lookup(b"").unwrap().title.as_deref(), Some("Empty"));
+    }
+
+    #[test]
+    fn merged_entries_replace_bundled_ones() {
+        let mut database = Database::parse(&format!("[{}]\ntitle = \"Old\"\n", HASH)).unwrap();
+        database.merge(Database::parse(&format!("[{}]\ntitle = \"New\"\n", HASH)).unwrap());
+        assert_eq!(database.len(), 1);
+        assert_eq!(database.get(HASH).unwrap().title.as_deref(), Some("New"));
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(Database::parse("title = \"x\"\n").is_err());
+        assert!(Database::parse("[abc]\ntitle = \"x\"\n").unwrap_err().contains("not a SHA-1 hash"));
+        assert!(Database::parse(&format!("[{}]\nspeed = 1\n", HASH)).unwrap_err().contains("unknown setting"));
+        assert!(Database::parse(&format!("[{}]\nvariant = \"nes\"\n", HASH)).is_err());
+        assert!(Database::parse(&format!("[{}]\ncycles-per-frame = 0\n", HASH)).is_err());
+        assert!(Database::parse(&format!("[{}.other]\n", HASH)).unwrap_err().contains("unknown table"));
+    }
+}
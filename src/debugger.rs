@@ -0,0 +1,757 @@
+//! Breakpoints, watchpoints and a small command language for debugging programs.
+//!
+//! A frontend passes every line the user types to [`Debugger::run_command`] and,
+//! while [`Debugger::is_running`], calls [`Debugger::run_frame`] in place of
+//! [`Chip8::run_frame`]. The debugger starts out paused.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use crate::chip8::{Chip8, Chip8Error};
+use crate::disasm::{self, Syntax};
+
+/// Number of bytes `dump` shows when no length is given.
+const DEFAULT_DUMP_LENGTH: usize = 0x40;
+
+/// Commands understood by [`Debugger::run_command`].
+pub const HELP: &str = "\
+Addresses and values are hexadecimal, with or without 0x; step counts are decimal.
+  step|s [N]                 execute N instructions (default 1)
+  continue|c                 run until a breakpoint or watchpoint is hit
+  until|u ADDR               run until the program counter reaches ADDR
+  break|b ADDR               stop before executing the instruction at ADDR
+  break|b op PATTERN         stop before an opcode matching PATTERN, e.g. D??? or 00E0
+  break|b REG OP VALUE       stop when e.g. `v3 == 10` or `i >= 300` becomes true
+  watch|w ADDR[-END]         stop after an instruction writes to ADDR (through END)
+  delete|d ID                remove a breakpoint or watchpoint
+  list|l                     list breakpoints and watchpoints
+  regs|r                     show the registers, stack, timers and next instruction
+  dump|x ADDR [LENGTH]       show LENGTH bytes of memory (default 40)
+  set REG VALUE              set v0-vf, i, pc, dt or st
+  poke ADDR BYTE...          write bytes to memory
+  help|h                     show this text
+  quit|q                     exit the emulator
+A breakpoint stops when its condition becomes true, and again only once it was false in between,
+so a jump to itself stops once. An empty line repeats the previous command.";
+
+/// A register that breakpoints can test and `set` can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    /// Parses a register name: `v0`-`vf`, `i`, `pc`, `dt` or `st`.
+    pub fn from_name(name: &str) -> Option<Register> {
+        match name.to_ascii_lowercase().as_str() {
+            "i" => Some(Register::Index),
+            "pc" => Some(Register::ProgramCounter),
+            "dt" => Some(Register::DelayTimer),
+            "st" => Some(Register::SoundTimer),
+            name if name.len() == 2 && name.starts_with('v') => {
+                u8::from_str_radix(&name[1..], 16).ok().map(Register::V)
+            },
+            _ => None
+        }
+    }
+
+    pub fn read(self, chip8: &Chip8) -> u16 {
+        match self {
+            Register::V(x) => chip8.registers()[x as usize] as u16,
+            Register::Index => chip8.index(),
+            Register::ProgramCounter => chip8.program_counter(),
+            Register::DelayTimer => chip8.delay_timer() as u16,
+            Register::SoundTimer => chip8.sound_timer() as u16,
+        }
+    }
+
+    /// Sets the register, failing if `value` does not fit in it.
+    pub fn write(self, chip8: &mut Chip8, value: u16) -> Result<(), String> {
+        let byte = || if value <= 0xFF { Ok(value as u8) } else { Err(format!("{} holds a single byte", self)) };
+        match self {
+            Register::V(x) => chip8.set_register(x as usize, byte()?),
+            Register::Index => chip8.set_index(value),
+            Register::ProgramCounter => chip8.set_program_counter(value),
+            Register::DelayTimer => chip8.set_delay_timer(byte()?),
+            Register::SoundTimer => chip8.set_sound_timer(byte()?),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::Index => write!(f, "i"),
+            Register::ProgramCounter => write!(f, "pc"),
+            Register::DelayTimer => write!(f, "dt"),
+            Register::SoundTimer => write!(f, "st"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Parses one of `==`, `!=`, `<`, `<=`, `>` and `>=`.
+    pub fn from_name(name: &str) -> Option<Comparison> {
+        match name {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            _ => None
+        }
+    }
+
+    pub fn holds(self, left: u16, right: u16) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+/// When a breakpoint stops execution. A breakpoint triggers when its condition becomes true and has
+/// to turn false again before it triggers another time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// The next instruction is at this address.
+    Address(u16),
+    /// The next opcode matches `value` in the bits set in `mask`.
+    Opcode { value: u16, mask: u16 },
+    /// The comparison holds.
+    Register { register: Register, comparison: Comparison, value: u16 },
+}
+
+impl Condition {
+    /// Parses an opcode pattern of four hex digits, where `?`, `_`, `x`, `y` and `n` match any nibble.
+    pub fn parse_opcode_pattern(pattern: &str) -> Option<Condition> {
+        if pattern.len() != 4 {
+            return None;
+        }
+        let mut value = 0;
+        let mut mask = 0;
+        for c in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            match c {
+                '?' | '_' | 'x' | 'X' | 'y' | 'Y' | 'n' | 'N' => {},
+                _ => {
+                    value |= c.to_digit(16)? as u16;
+                    mask |= 0xF;
+                }
+            }
+        }
+        Some(Condition::Opcode { value, mask })
+    }
+
+    fn holds(&self, chip8: &Chip8) -> bool {
+        match *self {
+            Condition::Address(address) => chip8.program_counter() == address,
+            Condition::Opcode { value, mask } => {
+                next_opcode(chip8).is_some_and(|op_code| op_code & mask == value)
+            },
+            Condition::Register { register, comparison, value } => {
+                comparison.holds(register.read(chip8), value)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Address(address) => write!(f, "at {:#06x}", address),
+            Condition::Opcode { value, mask } => {
+                write!(f, "opcode ")?;
+                for shift in (0..4).rev().map(|nibble| nibble * 4) {
+                    if (mask >> shift) & 0xF == 0 {
+                        write!(f, "?")?;
+                    } else {
+                        write!(f, "{:X}", (value >> shift) & 0xF)?;
+                    }
+                }
+                Ok(())
+            },
+            Condition::Register { register, comparison, value } => {
+                write!(f, "{} {} {:#x}", register, comparison, value)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    condition: Condition,
+    /// Whether the condition held after the previous instruction.
+    held: bool,
+}
+
+/// Why [`Debugger::run_frame`] or a `step` stopped.
+#[derive(Debug)]
+pub enum StopReason {
+    Breakpoint { id: usize, condition: Condition },
+    Watchpoint { id: usize, target: usize },
+    /// The program counter reached the address given to `until`.
+    Until(u16),
+    Exited,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint { id, condition } => write!(f, "Breakpoint {} hit: {}", id, condition),
+            StopReason::Watchpoint { id, target } => write!(f, "Watchpoint {} hit: write to {:#06x}", id, target),
+            StopReason::Until(address) => write!(f, "Reached {:#06x}", address),
+            StopReason::Exited => write!(f, "Program exited"),
+            StopReason::Error(e) => write!(f, "Execution halted: {}", e),
+        }
+    }
+}
+
+/// A parsed debugger command, see [`HELP`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(u32),
+    Continue,
+    Until(u16),
+    Break(Condition),
+    /// Watches the addresses from the first to the second, inclusive.
+    Watch(u16, u16),
+    Delete(usize),
+    List,
+    Registers,
+    Dump { start: u16, length: usize },
+    Set(Register, u16),
+    Poke { start: u16, bytes: Vec<u8> },
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (&name, args) = words.split_first().ok_or_else(|| String::from("Empty command"))?;
+        let command = match (name, args) {
+            ("step", []) | ("s", []) => Command::Step(1),
+            ("step", [count]) | ("s", [count]) => {
+                Command::Step(count.parse().map_err(|_| format!("Invalid step count \"{}\"", count))?)
+            },
+            ("continue", []) | ("c", []) => Command::Continue,
+            ("until", [address]) | ("u", [address]) => Command::Until(parse_number(address)?),
+            ("break", ["op", pattern]) | ("b", ["op", pattern]) => Command::Break(
+                Condition::parse_opcode_pattern(pattern)
+                    .ok_or_else(|| format!("Invalid opcode pattern \"{}\"", pattern))?
+            ),
+            ("break", [address]) | ("b", [address]) => Command::Break(Condition::Address(parse_number(address)?)),
+            ("break", [register, comparison, value]) | ("b", [register, comparison, value]) => {
+                Command::Break(Condition::Register {
+                    register: parse_register(register)?,
+                    comparison: Comparison::from_name(comparison)
+                        .ok_or_else(|| format!("Unknown comparison \"{}\"", comparison))?,
+                    value: parse_number(value)?,
+                })
+            },
+            ("watch", [range]) | ("w", [range]) => {
+                let mut bounds = range.splitn(2, '-');
+                let start = parse_number(bounds.next().unwrap_or(""))?;
+                let end = match bounds.next() {
+                    Some(end) => parse_number(end)?,
+                    None => start,
+                };
+                if end < start {
+                    return Err(format!("Invalid address range \"{}\"", range));
+                }
+                Command::Watch(start, end)
+            },
+            ("delete", [id]) | ("d", [id]) => {
+                Command::Delete(id.parse().map_err(|_| format!("Invalid breakpoint number \"{}\"", id))?)
+            },
+            ("list", []) | ("l", []) => Command::List,
+            ("regs", []) | ("r", []) => Command::Registers,
+            ("dump", [start]) | ("x", [start]) => {
+                Command::Dump { start: parse_number(start)?, length: DEFAULT_DUMP_LENGTH }
+            },
+            ("dump", [start, length]) | ("x", [start, length]) => {
+                Command::Dump { start: parse_number(start)?, length: parse_number(length)? as usize }
+            },
+            ("set", [register, value]) => Command::Set(parse_register(register)?, parse_number(value)?),
+            ("poke", [start, bytes @ ..]) if !bytes.is_empty() => {
+                let bytes = bytes.iter()
+                    .map(|byte| match parse_number(byte)? {
+                        value if value <= 0xFF => Ok(value as u8),
+                        _ => Err(format!("\"{}\" is not a byte", byte)),
+                    })
+                    .collect::<Result<Vec<u8>, String>>()?;
+                Command::Poke { start: parse_number(start)?, bytes }
+            },
+            ("help", []) | ("h", []) | ("?", []) => Command::Help,
+            ("quit", []) | ("q", []) => Command::Quit,
+            _ => return Err(format!("Unknown command \"{}\"; type help for a list", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal number \"{}\"", text))
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    Register::from_name(name).ok_or_else(|| format!("Unknown register \"{}\"", name))
+}
+
+/// The opcode at the program counter, if it lies in memory.
+fn next_opcode(chip8: &Chip8) -> Option<u16> {
+    let address = chip8.program_counter() as usize;
+    let memory = chip8.memory();
+    match (memory.get(address), memory.get(address + 1)) {
+        (Some(&high), Some(&low)) => Some((high as u16) << 8 | low as u16),
+        _ => None
+    }
+}
+
+/// Formats the registers, stack, timers and the next instruction.
+pub fn format_state(chip8: &Chip8) -> String {
+    let mut state = String::new();
+    let pc = chip8.program_counter();
+    let _ = writeln!(state, "PC={:#06x} I={:#06x} DT={:02x} ST={:02x} cycle={}",
+        pc, chip8.index(), chip8.delay_timer(), chip8.sound_timer(), chip8.cycle_count());
+    for row in chip8.registers().chunks(8).enumerate() {
+        let (row, values) = row;
+        let registers: Vec<String> = values.iter().enumerate()
+            .map(|(i, value)| format!("V{:X}={:02x}", row * 8 + i, value))
+            .collect();
+        let _ = writeln!(state, "{}", registers.join(" "));
+    }
+    let stack: Vec<String> = chip8.stack().iter().map(|address| format!("{:#06x}", address)).collect();
+    let _ = writeln!(state, "Stack: [{}]", stack.join(", "));
+    let next = match (next_opcode(chip8), disasm::format_at(chip8.memory(), pc as usize, Syntax::Octo)) {
+        (Some(op_code), Some((text, _))) => format!("{:04x}  {}", op_code, text),
+        _ => String::from("(outside memory)"),
+    };
+    if chip8.is_waiting_for_key() {
+        let _ = writeln!(state, "Waiting for a key press");
+    }
+    let _ = write!(state, "Next: {:#06x}  {}", pc, next);
+    state
+}
+
+/// Formats `length` bytes of memory from `start`, 16 per line.
+pub fn format_memory(chip8: &Chip8, start: u16, length: usize) -> String {
+    let memory = chip8.memory();
+    let start = start as usize;
+    let end = (start + length).min(memory.len());
+    let lines: Vec<String> = (start..end).step_by(16)
+        .map(|line| {
+            let bytes: Vec<String> = memory[line..(line + 16).min(end)].iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("{:#06x}: {}", line, bytes.join(" "))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Breakpoints and watchpoints on one machine, and whether it is currently running.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    watchpoints: BTreeMap<usize, (u16, u16)>,
+    /// The id handed out last; ids start at 1.
+    next_id: usize,
+    running: bool,
+    until: Option<u16>,
+    quit: bool,
+    last_command: Option<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Whether the frontend should keep calling [`Debugger::run_frame`].
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts running, as the `continue` command does.
+    pub fn resume(&mut self) {
+        self.running = true;
+    }
+
+    /// Stops running at the end of the current frame, as if a breakpoint was hit.
+    pub fn pause(&mut self) {
+        self.running = false;
+        self.until = None;
+    }
+
+    /// Whether the user asked to exit with `quit`.
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Parses and executes one line of input, returning the text to show the user.
+    pub fn run_command(&mut self, chip8: &mut Chip8, line: &str) -> Result<String, String> {
+        let line = match (line.trim(), &self.last_command) {
+            ("", Some(last)) => last.clone(),
+            ("", None) => return Ok(String::new()),
+            (line, _) => String::from(line),
+        };
+        let command = Command::parse(&line)?;
+        self.last_command = Some(line);
+        self.execute(chip8, command)
+    }
+
+    /// Executes a parsed command, see [`Debugger::run_command`].
+    pub fn execute(&mut self, chip8: &mut Chip8, command: Command) -> Result<String, String> {
+        let output = match command {
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if let Some(reason) = self.step(chip8) {
+                        return Ok(format!("{}\n{}", reason, format_state(chip8)));
+                    }
+                }
+                format_state(chip8)
+            },
+            Command::Continue => {
+                self.resume();
+                String::from("Continuing")
+            },
+            Command::Until(address) => {
+                self.until = Some(address);
+                self.running = true;
+                format!("Running until {:#06x}", address)
+            },
+            Command::Break(condition) => {
+                let id = self.take_id();
+                let held = condition.holds(chip8);
+                self.breakpoints.insert(id, Breakpoint { condition, held });
+                format!("Breakpoint {}: {}", id, condition)
+            },
+            Command::Watch(start, end) => {
+                let id = self.take_id();
+                self.watchpoints.insert(id, (start, end));
+                chip8.set_write_logging(true);
+                format!("Watchpoint {}: {}", id, format_range(start, end))
+            },
+            Command::Delete(id) => {
+                if self.breakpoints.remove(&id).is_none() && self.watchpoints.remove(&id).is_none() {
+                    return Err(format!("No breakpoint or watchpoint {}", id));
+                }
+                if self.watchpoints.is_empty() {
+                    chip8.set_write_logging(false);
+                }
+                format!("Deleted {}", id)
+            },
+            Command::List => {
+                let mut lines: Vec<(usize, String)> = self.breakpoints.iter()
+                    .map(|(id, breakpoint)| (*id, format!("{}: break {}", id, breakpoint.condition)))
+                    .chain(self.watchpoints.iter()
+                        .map(|(id, &(start, end))| (*id, format!("{}: watch {}", id, format_range(start, end)))))
+                    .collect();
+                lines.sort();
+                if lines.is_empty() {
+                    String::from("No breakpoints or watchpoints")
+                } else {
+                    lines.into_iter().map(|(_, line)| line).collect::<Vec<_>>().join("\n")
+                }
+            },
+            Command::Registers => format_state(chip8),
+            Command::Dump { start, length } => format_memory(chip8, start, length),
+            Command::Set(register, value) => {
+                register.write(chip8, value)?;
+                format!("{} = {:#x}", register, value)
+            },
+            Command::Poke { start, bytes } => {
+                // Checked up front so a poke that doesn't fit leaves memory untouched.
+                let end = start as usize + bytes.len() - 1;
+                if end >= chip8.memory().len() {
+                    return Err(format!("{:#06x} is outside memory", (start as usize).max(chip8.memory().len())));
+                }
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    chip8.set_memory(start as usize + offset, byte);
+                }
+                format_memory(chip8, start, bytes.len())
+            },
+            Command::Help => String::from(HELP),
+            Command::Quit => {
+                self.quit = true;
+                self.running = false;
+                String::new()
+            },
+        };
+        Ok(output)
+    }
+
+    /// Executes a single instruction and reports whether it triggered a stop.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        match chip8.emulate_cycle() {
+            Ok(()) if chip8.has_exited() => Some(StopReason::Exited),
+            Ok(()) => self.check(chip8),
+            Err(e) => Some(StopReason::Error(e)),
+        }
+    }
+
+    /// Runs one frame like [`Chip8::run_frame`], pausing after the first instruction that hits a
+    /// breakpoint or watchpoint, or when the program exits or fails.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        let mut reason = None;
+        let stop = match chip8.run_frame_until(|chip8| {
+            reason = self.check(chip8);
+            reason.is_some()
+        }) {
+            Ok(_) if chip8.has_exited() => Some(StopReason::Exited),
+            Ok(_) => reason,
+            Err(e) => Some(StopReason::Error(e)),
+        };
+        if stop.is_some() {
+            self.pause();
+        }
+        stop
+    }
+
+    fn take_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Checks the state after an instruction against the watchpoints, `until` and the breakpoints.
+    fn check(&mut self, chip8: &Chip8) -> Option<StopReason> {
+        let mut reason = None;
+        for &target in chip8.memory_writes() {
+            let hit = self.watchpoints.iter()
+                .find(|(_, &(start, end))| (start as usize..=end as usize).contains(&target));
+            if let Some((&id, _)) = hit {
+                reason = Some(StopReason::Watchpoint { id, target });
+                break;
+            }
+        }
+        if reason.is_none() && self.until == Some(chip8.program_counter()) {
+            self.until = None;
+            reason = Some(StopReason::Until(chip8.program_counter()));
+        }
+        // Every breakpoint is evaluated so each keeps track of whether its condition held before.
+        for (&id, breakpoint) in self.breakpoints.iter_mut() {
+            let holds = breakpoint.condition.holds(chip8);
+            let triggered = holds && !breakpoint.held;
+            breakpoint.held = holds;
+            if triggered && reason.is_none() {
+                reason = Some(StopReason::Breakpoint { id, condition: breakpoint.condition });
+            }
+        }
+        reason
+    }
+}
+
+fn format_range(start: u16, end: u16) -> String {
+    if start == end {
+        format!("{:#06x}", start)
+    } else {
+        format!("{:#06x}-{:#06x}", start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::with_seed;
+
+    /// Runs frames until the debugger stops, giving up after `frames`.
+    fn run_until_stop(debugger: &mut Debugger, chip8: &mut Chip8, frames: usize) -> Option<StopReason> {
+        debugger.resume();
+        (0..frames).find_map(|_| debugger.run_frame(chip8))
+    }
+
+    fn command(line: &str) -> Command {
+        Command::parse(line).unwrap()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(command("s"), Command::Step(1));
+        assert_eq!(command("step 10"), Command::Step(10));
+        assert_eq!(command("c"), Command::Continue);
+        assert_eq!(command("u 0x20a"), Command::Until(0x20A));
+        assert_eq!(command("b 200"), Command::Break(Condition::Address(0x200)));
+        assert_eq!(command("break op D???"), Command::Break(Condition::Opcode { value: 0xD000, mask: 0xF000 }));
+        assert_eq!(command("b op 8xy6"), Command::Break(Condition::Opcode { value: 0x8006, mask: 0xF00F }));
+        assert_eq!(
+            command("b v3 == 10"),
+            Command::Break(Condition::Register { register: Register::V(3), comparison: Comparison::Equal, value: 0x10 })
+        );
+        assert_eq!(
+            command("b I >= 300"),
+            Command::Break(Condition::Register { register: Register::Index, comparison: Comparison::GreaterOrEqual, value: 0x300 })
+        );
+        assert_eq!(command("w 300-30F"), Command::Watch(0x300, 0x30F));
+        assert_eq!(command("watch 300"), Command::Watch(0x300, 0x300));
+        assert_eq!(command("d 2"), Command::Delete(2));
+        assert_eq!(command("l"), Command::List);
+        assert_eq!(command("regs"), Command::Registers);
+        assert_eq!(command("x 200"), Command::Dump { start: 0x200, length: DEFAULT_DUMP_LENGTH });
+        assert_eq!(command("dump 200 10"), Command::Dump { start: 0x200, length: 0x10 });
+        assert_eq!(command("set pc 300"), Command::Set(Register::ProgramCounter, 0x300));
+        assert_eq!(command("poke 300 1 ff"), Command::Poke { start: 0x300, bytes: vec![0x01, 0xFF] });
+        assert_eq!(command("?"), Command::Help);
+        assert_eq!(command("  quit  "), Command::Quit);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        for line in [
+            "", "step x", "step -1", "c now", "u zz", "b op D??", "b op G000", "b v3 =~ 1", "b vg == 1", "b v3 ==",
+            "w 30F-300", "w -300", "d one", "x 200 10 20", "set v0", "poke 300", "poke 300 1 100", "frobnicate",
+        ] {
+            assert!(Command::parse(line).is_err(), "\"{}\" was accepted", line);
+        }
+    }
+
+    #[test]
+    fn poke_outside_memory_changes_nothing() {
+        let mut chip8 = with_seed(&[], 0);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_command(&mut chip8, "poke ffe 1 2 3"), Err(String::from("0x1000 is outside memory")));
+        assert_eq!(chip8.memory()[0xFFE..], [0, 0]);
+        assert!(debugger.run_command(&mut chip8, "poke ffe 1 2").is_ok());
+        assert_eq!(chip8.memory()[0xFFE..], [1, 2]);
+        assert!(debugger.run_command(&mut chip8, "set v0 100").is_err());
+    }
+
+    #[test]
+    fn address_breakpoint_stops_once_per_visit() {
+        let program = [
+            0x70, 0x01, // 0x200: v0 += 1
+            0x12, 0x00, // 0x202: jump 0x200
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        let mut debugger = Debugger::new();
+        debugger.run_command(&mut chip8, "b 202").unwrap();
+        let stop = run_until_stop(&mut debugger, &mut chip8, 1);
+        assert!(matches!(stop, Some(StopReason::Breakpoint { id: 1, condition: Condition::Address(0x202) })));
+        assert!(!debugger.is_running());
+        assert_eq!(chip8.cycle_count(), 1);
+        run_until_stop(&mut debugger, &mut chip8, 1).unwrap();
+        assert_eq!(chip8.cycle_count(), 3);
+        assert_eq!(chip8.registers()[0], 2);
+
+        // A jump to itself keeps the condition true, so it only stops the first time.
+        let mut chip8 = with_seed(&[0x60, 0x05, 0x12, 0x02], 0);
+        let mut debugger = Debugger::new();
+        debugger.run_command(&mut chip8, "b 202").unwrap();
+        assert!(run_until_stop(&mut debugger, &mut chip8, 1).is_some());
+        assert!(run_until_stop(&mut debugger, &mut chip8, 10).is_none());
+        assert!(debugger.is_running());
+    }
+
+    #[test]
+    fn opcode_breakpoint_stops_once_per_run_of_matches() {
+        let program = [
+            0x60, 0x00, // 0x200: v0 := 0
+            0xD0, 0x05, // 0x202: sprite v0 v0 5
+            0xD0, 0x05, // 0x204: sprite v0 v0 5
+            0x12, 0x00, // 0x206: jump 0x200
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        let mut debugger = Debugger::new();
+        debugger.run_command(&mut chip8, "b op D???").unwrap();
+        run_until_stop(&mut debugger, &mut chip8, 1).unwrap();
+        assert_eq!((chip8.program_counter(), chip8.cycle_count()), (0x202, 1));
+        run_until_stop(&mut debugger, &mut chip8, 1).unwrap();
+        assert_eq!((chip8.program_counter(), chip8.cycle_count()), (0x202, 5));
+    }
+
+    #[test]
+    fn register_breakpoint_stops_when_the_comparison_becomes_true() {
+        let program = [
+            0x73, 0x01, // 0x200: v3 += 1
+            0x12, 0x00, // 0x202: jump 0x200
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        let mut debugger = Debugger::new();
+        debugger.run_command(&mut chip8, "b v3 >= f0").unwrap();
+        let stop = run_until_stop(&mut debugger, &mut chip8, 1000).unwrap();
+        assert_eq!(stop.to_string(), "Breakpoint 1 hit: v3 >= 0xf0");
+        assert_eq!((chip8.registers()[3], chip8.cycle_count()), (0xF0, 479));
+        // Still true for the next 15 increments, then v3 wraps around to 0 before it holds again.
+        run_until_stop(&mut debugger, &mut chip8, 1000).unwrap();
+        assert_eq!((chip8.registers()[3], chip8.cycle_count()), (0xF0, 479 + 512));
+    }
+
+    #[test]
+    fn watchpoint_stops_after_a_write() {
+        let program = [
+            0x60, 0x7B, // 0x200: v0 := 123
+            0xA3, 0x00, // 0x202: i := 0x300
+            0xF0, 0x33, // 0x204: bcd v0
+            0x12, 0x06, // 0x206: jump 0x206
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        let mut debugger = Debugger::new();
+        debugger.run_command(&mut chip8, "w 310-320").unwrap();
+        debugger.run_command(&mut chip8, "w 301").unwrap();
+        let stop = run_until_stop(&mut debugger, &mut chip8, 1);
+        assert!(matches!(stop, Some(StopReason::Watchpoint { id: 2, target: 0x301 })));
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(chip8.memory()[0x300..0x303], [1, 2, 3]);
+
+        debugger.run_command(&mut chip8, "d 2").unwrap();
+        assert_eq!(debugger.run_command(&mut chip8, "l"), Ok(String::from("1: watch 0x0310-0x0320")));
+        assert!(debugger.run_command(&mut chip8, "d 2").is_err());
+    }
+
+    #[test]
+    fn until_stops_at_the_address_once() {
+        let program = [
+            0x70, 0x01, // 0x200: v0 += 1
+            0x70, 0x01, // 0x202: v0 += 1
+            0x70, 0x01, // 0x204: v0 += 1
+            0x12, 0x00, // 0x206: jump 0x200
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_command(&mut chip8, "u 204"), Ok(String::from("Running until 0x0204")));
+        assert!(debugger.is_running());
+        let stop = (0..1).find_map(|_| debugger.run_frame(&mut chip8));
+        assert!(matches!(stop, Some(StopReason::Until(0x204))));
+        assert_eq!(chip8.cycle_count(), 2);
+        assert!(run_until_stop(&mut debugger, &mut chip8, 10).is_none());
+    }
+}
@@ -0,0 +1,412 @@
+//! Disassembler producing annotated listings of CHIP-8 programs.
+//!
+//! [`disassemble`] follows the control flow from the entry point to tell code from
+//! data, labels jump and call targets, and prints everything else as data bytes
+//! with a sprite preview.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::instruction::{decode, Instruction};
+
+/// The assembly dialect to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo, e.g. `v3 += 0x01`.
+    Octo,
+    /// The classic mnemonics from Cowgod's technical reference, e.g. `ADD V3, #01`.
+    Classic,
+}
+
+impl Syntax {
+    /// Parses a syntax name as used on the command line: `octo` or `classic`.
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "octo" => Some(Syntax::Octo),
+            "classic" => Some(Syntax::Classic),
+            _ => None
+        }
+    }
+}
+
+/// Formats a single instruction. `long_address` is the word following `F000`, and
+/// `label` names the target of jumps and calls.
+pub fn format_instruction(
+    instruction: Instruction,
+    syntax: Syntax,
+    long_address: Option<u16>,
+    label: &dyn Fn(u16) -> String,
+) -> String {
+    let long = long_address.map_or(String::from("????"), |address| format!("{:#06x}", address));
+    match syntax {
+        Syntax::Octo => format_octo(instruction, &long, label),
+        Syntax::Classic => format_classic(instruction, &long, label),
+    }
+}
+
+/// Formats the instruction at `address` in `memory`, with plain addresses as targets. Returns the
+/// text and the number of bytes it occupies, or `None` if its opcode lies outside `memory`.
+/// Unknown opcodes are shown as data.
+pub fn format_at(memory: &[u8], address: usize, syntax: Syntax) -> Option<(String, usize)> {
+    let word_at = |address: usize| match (memory.get(address), memory.get(address + 1)) {
+        (Some(&high), Some(&low)) => Some((high as u16) << 8 | low as u16),
+        _ => None
+    };
+    let op_code = word_at(address)?;
+    let formatted = match decode(op_code) {
+        Ok(instruction) => {
+            let long_address = if instruction.size() == 4 { word_at(address + 2) } else { None };
+            let text = format_instruction(instruction, syntax, long_address, &|target| format!("{:#05x}", target));
+            (text, instruction.size() as usize)
+        },
+        Err(_) => match syntax {
+            Syntax::Octo => (format!("{:#04x} {:#04x}", op_code >> 8, op_code & 0xFF), 2),
+            Syntax::Classic => (format!("DW #{:04X}", op_code), 2),
+        },
+    };
+    Some(formatted)
+}
+
+fn format_octo(instruction: Instruction, long: &str, label: &dyn Fn(u16) -> String) -> String {
+    use crate::instruction::Instruction::*;
+    match instruction {
+        ScrollDown { n } => format!("scroll-down {}", n),
+        ScrollUp { n } => format!("scroll-up {}", n),
+        ClearScreen => String::from("clear"),
+        Return => String::from("return"),
+        ScrollRight => String::from("scroll-right"),
+        ScrollLeft => String::from("scroll-left"),
+        Exit => String::from("exit"),
+        LowRes => String::from("lores"),
+        HighRes => String::from("hires"),
+        Jump { nnn } => format!("jump {}", label(nnn)),
+        Call { nnn } => format!(":call {}", label(nnn)),
+        SkipEqImm { x, nn } => format!("if v{:x} != {:#04x} then", x, nn),
+        SkipNotEqImm { x, nn } => format!("if v{:x} == {:#04x} then", x, nn),
+        SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        LoadImm { x, nn } => format!("v{:x} := {:#04x}", x, nn),
+        AddImm { x, nn } => format!("v{:x} += {:#04x}", x, nn),
+        Move { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Add { x, y } => format!("v{:x} += v{:x}", x, y),
+        Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        SubReverse { x, y } => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        SkipNotEqReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        LoadIndex { nnn } => format!("i := {}", label(nnn)),
+        JumpOffset { nnn } => format!("jump0 {}", label(nnn)),
+        Random { x, nn } => format!("v{:x} := random {:#04x}", x, nn),
+        Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipKeyPressed { x } => format!("if v{:x} -key then", x),
+        SkipKeyNotPressed { x } => format!("if v{:x} key then", x),
+        LoadIndexLong => format!("i := long {}", long),
+        SelectPlanes { n } => format!("plane {}", n),
+        LoadAudioPattern => String::from("audio"),
+        GetDelay { x } => format!("v{:x} := delay", x),
+        WaitKey { x } => format!("v{:x} := key", x),
+        SetDelay { x } => format!("delay := v{:x}", x),
+        SetSound { x } => format!("buzzer := v{:x}", x),
+        AddIndex { x } => format!("i += v{:x}", x),
+        LoadFont { x } => format!("i := hex v{:x}", x),
+        LoadBigFont { x } => format!("i := bighex v{:x}", x),
+        StoreBcd { x } => format!("bcd v{:x}", x),
+        SetPitch { x } => format!("pitch := v{:x}", x),
+        Store { x } => format!("save v{:x}", x),
+        Load { x } => format!("load v{:x}", x),
+        SaveFlags { x } => format!("saveflags v{:x}", x),
+        LoadFlags { x } => format!("loadflags v{:x}", x),
+    }
+}
+
+fn format_classic(instruction: Instruction, long: &str, label: &dyn Fn(u16) -> String) -> String {
+    use crate::instruction::Instruction::*;
+    match instruction {
+        ScrollDown { n } => format!("SCD {}", n),
+        ScrollUp { n } => format!("SCU {}", n),
+        ClearScreen => String::from("CLS"),
+        Return => String::from("RET"),
+        ScrollRight => String::from("SCR"),
+        ScrollLeft => String::from("SCL"),
+        Exit => String::from("EXIT"),
+        LowRes => String::from("LOW"),
+        HighRes => String::from("HIGH"),
+        Jump { nnn } => format!("JP {}", label(nnn)),
+        Call { nnn } => format!("CALL {}", label(nnn)),
+        SkipEqImm { x, nn } => format!("SE V{:X}, #{:02X}", x, nn),
+        SkipNotEqImm { x, nn } => format!("SNE V{:X}, #{:02X}", x, nn),
+        SkipEqReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        SaveRange { x, y } => format!("SAVE V{:X} - V{:X}", x, y),
+        LoadRange { x, y } => format!("LOAD V{:X} - V{:X}", x, y),
+        LoadImm { x, nn } => format!("LD V{:X}, #{:02X}", x, nn),
+        AddImm { x, nn } => format!("ADD V{:X}, #{:02X}", x, nn),
+        Move { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        SubReverse { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        SkipNotEqReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        LoadIndex { nnn } => format!("LD I, {}", label(nnn)),
+        JumpOffset { nnn } => format!("JP V0, {}", label(nnn)),
+        Random { x, nn } => format!("RND V{:X}, #{:02X}", x, nn),
+        Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        SkipKeyPressed { x } => format!("SKP V{:X}", x),
+        SkipKeyNotPressed { x } => format!("SKNP V{:X}", x),
+        LoadIndexLong => format!("LD I, LONG {}", long),
+        SelectPlanes { n } => format!("PLANE {}", n),
+        LoadAudioPattern => String::from("AUDIO"),
+        GetDelay { x } => format!("LD V{:X}, DT", x),
+        WaitKey { x } => format!("LD V{:X}, K", x),
+        SetDelay { x } => format!("LD DT, V{:X}", x),
+        SetSound { x } => format!("LD ST, V{:X}", x),
+        AddIndex { x } => format!("ADD I, V{:X}", x),
+        LoadFont { x } => format!("LD F, V{:X}", x),
+        LoadBigFont { x } => format!("LD HF, V{:X}", x),
+        StoreBcd { x } => format!("LD B, V{:X}", x),
+        SetPitch { x } => format!("PITCH V{:X}", x),
+        Store { x } => format!("LD [I], V{:X}", x),
+        Load { x } => format!("LD V{:X}, [I]", x),
+        SaveFlags { x } => format!("LD R, V{:X}", x),
+        LoadFlags { x } => format!("LD V{:X}, R", x),
+    }
+}
+
+/// What the control flow analysis found out about a program.
+#[derive(Debug, Clone, Default)]
+pub struct CodeMap {
+    /// Addresses of reachable instructions.
+    pub instructions: BTreeSet<u16>,
+    /// Targets of jumps, keyed by address, with whether any of them is a subroutine call.
+    pub branch_targets: BTreeMap<u16, bool>,
+    /// Addresses loaded into I by `ANNN` or `F000 NNNN`, most likely sprite or variable data.
+    pub data_references: BTreeSet<u16>,
+}
+
+/// Follows every statically known path through `rom` loaded at `load_address`.
+///
+/// `BNNN` jumps depend on V0 at runtime, so their targets are only labelled, not followed.
+pub fn analyze(rom: &[u8], load_address: u16) -> CodeMap {
+    let end = load_address as usize + rom.len();
+    let word_at = |address: u16| -> Option<u16> {
+        let offset = (address as usize).checked_sub(load_address as usize)?;
+        if address as usize + 1 >= end {
+            return None;
+        }
+        Some((rom[offset] as u16) << 8 | rom[offset + 1] as u16)
+    };
+
+    let mut map = CodeMap::default();
+    let mut pending = vec![load_address];
+    while let Some(address) = pending.pop() {
+        if map.instructions.contains(&address) {
+            continue;
+        }
+        let instruction = match word_at(address).map(decode) {
+            Some(Ok(instruction)) => instruction,
+            _ => continue,
+        };
+        map.instructions.insert(address);
+        let next = address.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Jump { nnn } => {
+                map.branch_targets.entry(nnn).or_insert(false);
+                pending.push(nnn);
+            },
+            Instruction::Call { nnn } => {
+                map.branch_targets.insert(nnn, true);
+                pending.push(nnn);
+                pending.push(next);
+            },
+            Instruction::JumpOffset { nnn } => {
+                map.branch_targets.entry(nnn).or_insert(false);
+            },
+            Instruction::Return | Instruction::Exit => {},
+            Instruction::LoadIndex { nnn } => {
+                map.data_references.insert(nnn);
+                pending.push(next);
+            },
+            Instruction::LoadIndexLong => {
+                if let Some(target) = word_at(address.wrapping_add(2)) {
+                    map.data_references.insert(target);
+                }
+                pending.push(next);
+            },
+            skip if skip.is_skip() => {
+                pending.push(next);
+                let skipped_size = match word_at(next).map(decode) {
+                    Some(Ok(skipped)) => skipped.size(),
+                    _ => 2,
+                };
+                pending.push(next.wrapping_add(skipped_size));
+            },
+            _ => pending.push(next),
+        }
+    }
+    map
+}
+
+fn label_name(address: u16, map: &CodeMap) -> String {
+    match map.branch_targets.get(&address) {
+        Some(true) => format!("sub_{:03x}", address),
+        Some(false) => format!("label_{:03x}", address),
+        None if map.data_references.contains(&address) => format!("data_{:03x}", address),
+        None => format!("{:#05x}", address),
+    }
+}
+
+/// Produces an annotated listing of `rom` loaded at `load_address`.
+pub fn disassemble(rom: &[u8], load_address: u16, syntax: Syntax) -> String {
+    let map = analyze(rom, load_address);
+    let label = |address: u16| label_name(address, &map);
+    let byte_at = |address: usize| rom[address - load_address as usize];
+    let end = load_address as usize + rom.len();
+
+    let labelled = |address: u16| map.branch_targets.contains_key(&address) || map.data_references.contains(&address);
+    let comment = match syntax {
+        Syntax::Octo => '#',
+        Syntax::Classic => ';',
+    };
+
+    let mut listing = String::new();
+    let mut address = load_address as usize;
+    while address < end {
+        let address16 = address as u16;
+        let instruction = if map.instructions.contains(&address16) {
+            let op_code = (byte_at(address) as u16) << 8 | byte_at(address + 1) as u16;
+            Some((op_code, decode(op_code).expect("analysis only records decodable instructions")))
+        } else {
+            None
+        };
+        let size = instruction.map_or(1, |(_, instruction)| instruction.size() as usize);
+        if labelled(address16) {
+            let _ = writeln!(listing, "{}:", label(address16));
+        }
+        // A target in the middle of an instruction can't get a line of its own, but its label still needs defining.
+        for inside in (address + 1..address + size).filter(|&inside| inside < end && labelled(inside as u16)) {
+            let _ = writeln!(listing, "{}: {} {:#05x}, inside the next instruction", label(inside as u16), comment, inside);
+        }
+        match instruction {
+            Some((op_code, instruction)) => {
+                let long_address = if instruction.size() == 4 && address + 3 < end {
+                    Some((byte_at(address + 2) as u16) << 8 | byte_at(address + 3) as u16)
+                } else {
+                    None
+                };
+                let text = format_instruction(instruction, syntax, long_address, &label);
+                match long_address {
+                    Some(long) => {
+                        let _ = writeln!(listing, "    {:#05x}: {:04X} {:04X}  {}", address, op_code, long, text);
+                    },
+                    None => {
+                        let _ = writeln!(listing, "    {:#05x}: {:04X}       {}", address, op_code, text);
+                    },
+                }
+            },
+            None => {
+                let byte = byte_at(address);
+                let sprite: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+                let data = match syntax {
+                    Syntax::Octo => format!("{:#04x}", byte),
+                    Syntax::Classic => format!("DB #{:02X}", byte),
+                };
+                let _ = writeln!(listing, "    {:#05x}: {:02X}         {:<16} {} {}", address, byte, data, comment, sprite);
+            },
+        }
+        address += size;
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reaches everything but 0x208 through a skip, a jump and a call, and loads a sprite at 0x20E.
+    const PROGRAM: [u8; 18] = [
+        0x30, 0x00, // 0x200: if v0 != 0 then
+        0x12, 0x0A, // 0x202: jump 0x20A
+        0x22, 0x10, // 0x204: call 0x210
+        0x12, 0x06, // 0x206: jump 0x206
+        0xFF, 0xFF, // 0x208: unreachable
+        0xA2, 0x0E, // 0x20A: i := 0x20E
+        0x00, 0xFD, // 0x20C: exit
+        0x3C, 0x7E, // 0x20E: sprite
+        0x00, 0xEE, // 0x210: return
+    ];
+
+    fn lines(listing: &str) -> Vec<&str> {
+        listing.lines().collect()
+    }
+
+    #[test]
+    fn follows_skips_calls_and_jumps() {
+        let map = analyze(&PROGRAM, 0x200);
+        assert_eq!(map.instructions.into_iter().collect::<Vec<_>>(), [0x200, 0x202, 0x204, 0x206, 0x20A, 0x20C, 0x210]);
+        assert_eq!(map.branch_targets.into_iter().collect::<Vec<_>>(), [(0x206, false), (0x20A, false), (0x210, true)]);
+        assert_eq!(map.data_references.into_iter().collect::<Vec<_>>(), [0x20E]);
+    }
+
+    #[test]
+    fn lists_octo_syntax_with_labels() {
+        let listing = disassemble(&PROGRAM, 0x200, Syntax::Octo);
+        assert_eq!(lines(&listing), [
+            "    0x200: 3000       if v0 != 0x00 then",
+            "    0x202: 120A       jump label_20a",
+            "    0x204: 2210       :call sub_210",
+            "label_206:",
+            "    0x206: 1206       jump label_206",
+            "    0x208: FF         0xff             # ########",
+            "    0x209: FF         0xff             # ########",
+            "label_20a:",
+            "    0x20a: A20E       i := data_20e",
+            "    0x20c: 00FD       exit",
+            "data_20e:",
+            "    0x20e: 3C         0x3c             # ..####..",
+            "    0x20f: 7E         0x7e             # .######.",
+            "sub_210:",
+            "    0x210: 00EE       return",
+        ]);
+    }
+
+    #[test]
+    fn lists_classic_syntax_with_labels() {
+        let listing = disassemble(&PROGRAM, 0x200, Syntax::Classic);
+        let lines = lines(&listing);
+        assert_eq!(lines[1], "    0x202: 120A       JP label_20a");
+        assert_eq!(lines[2], "    0x204: 2210       CALL sub_210");
+        assert_eq!(lines[5], "    0x208: FF         DB #FF           ; ########");
+        assert_eq!(lines[8], "    0x20a: A20E       LD I, data_20e");
+        assert_eq!(lines[11], "    0x20e: 3C         DB #3C           ; ..####..");
+    }
+
+    #[test]
+    fn labels_targets_inside_an_instruction() {
+        let program = [
+            0x30, 0x00, // 0x200: if v0 != 0 then
+            0x12, 0x05, // 0x202: jump 0x205
+            0x60, 0x12, // 0x204: v0 := 0x12, whose second byte starts jump 0x200
+            0x00, 0xE0, // 0x206: clear
+        ];
+        let map = analyze(&program, 0x200);
+        assert!(map.instructions.contains(&0x205));
+        let listing = disassemble(&program, 0x200, Syntax::Octo);
+        assert_eq!(lines(&listing), [
+            "label_200:",
+            "    0x200: 3000       if v0 != 0x00 then",
+            "    0x202: 1205       jump label_205",
+            "label_205: # 0x205, inside the next instruction",
+            "    0x204: 6012       v0 := 0x12",
+            "    0x206: 00E0       clear",
+        ]);
+        let classic = disassemble(&program, 0x200, Syntax::Classic);
+        assert!(classic.contains("label_205: ; 0x205, inside the next instruction\n"));
+    }
+}
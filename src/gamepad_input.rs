@@ -0,0 +1,205 @@
+//! Game controller input. Piston's glutin backend reports no controller events, so controllers
+//! are read with gilrs and its events translated to piston's, which [`Keypad`] then handles like
+//! any window backend's. Without the `gamepad` feature this is a stub that always fails to open.
+//!
+//! [`Keypad`]: chip8_rust::keymap::Keypad
+
+use piston::input::{Button, ControllerAxisArgs};
+
+#[cfg(feature = "gamepad")]
+pub use self::gilrs_input::Gamepads;
+
+/// A controller event in piston's terms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub enum GamepadEvent {
+    /// A button or the d-pad (hat 0) was pressed or released.
+    Button(Button, bool),
+    Axis(ControllerAxisArgs),
+    /// The controller with this id was unplugged.
+    Disconnected(i32),
+}
+
+#[cfg(not(feature = "gamepad"))]
+pub struct Gamepads;
+
+#[cfg(not(feature = "gamepad"))]
+impl Gamepads {
+    pub fn new() -> Result<Gamepads, String> {
+        Err(String::from("built without the \"gamepad\" feature"))
+    }
+
+    pub fn poll(&mut self) -> Vec<GamepadEvent> {
+        Vec::new()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+mod gilrs_input {
+    use std::collections::HashMap;
+
+    use gilrs::{Axis, Button as GilrsButton, EventType, Gilrs};
+    use piston::input::{Button, ControllerAxisArgs, ControllerButton, ControllerHat, HatState};
+
+    use super::GamepadEvent;
+
+    /// Buttons in the order of SDL's game controller layout, so `Gamepad0` is the bottom face button.
+    /// The d-pad is hat 0 instead.
+    const BUTTONS: [GilrsButton; 15] = [
+        GilrsButton::South,
+        GilrsButton::East,
+        GilrsButton::West,
+        GilrsButton::North,
+        GilrsButton::Select,
+        GilrsButton::Mode,
+        GilrsButton::Start,
+        GilrsButton::LeftThumb,
+        GilrsButton::RightThumb,
+        GilrsButton::LeftTrigger,
+        GilrsButton::RightTrigger,
+        GilrsButton::LeftTrigger2,
+        GilrsButton::RightTrigger2,
+        GilrsButton::C,
+        GilrsButton::Z,
+    ];
+
+    /// Axes in the order of SDL's game controller layout: the two sticks, then the analog triggers.
+    const AXES: [Axis; 6] = [Axis::LeftStickX, Axis::LeftStickY, Axis::RightStickX, Axis::RightStickY, Axis::LeftZ, Axis::RightZ];
+
+    /// Every connected game controller, including ones plugged in later.
+    pub struct Gamepads {
+        gilrs: Gilrs,
+        dpads: DPads,
+    }
+
+    impl Gamepads {
+        pub fn new() -> Result<Gamepads, String> {
+            let gilrs = Gilrs::new().map_err(|e| e.to_string())?;
+            Ok(Gamepads { gilrs, dpads: DPads::default() })
+        }
+
+        /// The events since the last call.
+        pub fn poll(&mut self) -> Vec<GamepadEvent> {
+            let mut events = Vec::new();
+            while let Some(event) = self.gilrs.next_event() {
+                events.extend(self.dpads.translate(usize::from(event.id) as i32, event.event));
+            }
+            events
+        }
+    }
+
+    /// The d-pad directions held on each controller. gilrs reports them as four buttons
+    /// but piston as one hat, whose state holds all of them.
+    #[derive(Debug, Default)]
+    struct DPads {
+        held: HashMap<i32, [bool; 4]>,
+    }
+
+    impl DPads {
+        fn translate(&mut self, id: i32, event: EventType) -> Option<GamepadEvent> {
+            match event {
+                EventType::ButtonPressed(button, _) => self.button(id, button, true),
+                EventType::ButtonReleased(button, _) => self.button(id, button, false),
+                EventType::AxisChanged(axis, position, _) => self.axis(id, axis, position),
+                EventType::Disconnected => Some(self.disconnected(id)),
+                _ => None
+            }
+        }
+
+        fn axis(&mut self, id: i32, axis: Axis, position: f32) -> Option<GamepadEvent> {
+            let index = AXES.iter().position(|&candidate| candidate == axis)?;
+            // gilrs' Y axes point up, SDL's and so piston's point down.
+            let position = match axis {
+                Axis::LeftStickY | Axis::RightStickY => -position,
+                _ => position,
+            };
+            Some(GamepadEvent::Axis(ControllerAxisArgs::new(id, index as u8, position as f64)))
+        }
+
+        fn disconnected(&mut self, id: i32) -> GamepadEvent {
+            self.held.remove(&id);
+            GamepadEvent::Disconnected(id)
+        }
+
+        fn button(&mut self, id: i32, button: GilrsButton, pressed: bool) -> Option<GamepadEvent> {
+            let direction = [GilrsButton::DPadUp, GilrsButton::DPadDown, GilrsButton::DPadLeft, GilrsButton::DPadRight]
+                .iter()
+                .position(|&candidate| candidate == button);
+            match direction {
+                Some(direction) => {
+                    let held = self.held.entry(id).or_default();
+                    held[direction] = pressed;
+                    let state = hat_state(*held);
+                    Some(GamepadEvent::Button(Button::Hat(ControllerHat::new(id, 0, state)), state != HatState::Centered))
+                },
+                None => {
+                    let index = BUTTONS.iter().position(|&candidate| candidate == button)?;
+                    Some(GamepadEvent::Button(Button::Controller(ControllerButton::new(id, index as u8)), pressed))
+                },
+            }
+        }
+    }
+
+    /// The hat state for held up, down, left and right directions. Opposite directions cancel out.
+    fn hat_state([up, down, left, right]: [bool; 4]) -> HatState {
+        match (up && !down, down && !up, left && !right, right && !left) {
+            (true, _, true, _) => HatState::LeftUp,
+            (true, _, _, true) => HatState::RightUp,
+            (_, true, true, _) => HatState::LeftDown,
+            (_, true, _, true) => HatState::RightDown,
+            (true, _, _, _) => HatState::Up,
+            (_, true, _, _) => HatState::Down,
+            (_, _, true, _) => HatState::Left,
+            (_, _, _, true) => HatState::Right,
+            _ => HatState::Centered,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn hat(id: i32, state: HatState) -> Option<GamepadEvent> {
+            Some(GamepadEvent::Button(Button::Hat(ControllerHat::new(id, 0, state)), state != HatState::Centered))
+        }
+
+        #[test]
+        fn buttons_follow_the_sdl_layout() {
+            let mut dpads = DPads::default();
+            let pressed = dpads.button(3, GilrsButton::West, true);
+            assert_eq!(pressed, Some(GamepadEvent::Button(Button::Controller(ControllerButton::new(3, 2)), true)));
+            let released = dpads.button(3, GilrsButton::South, false);
+            assert_eq!(released, Some(GamepadEvent::Button(Button::Controller(ControllerButton::new(3, 0)), false)));
+            assert_eq!(dpads.button(3, GilrsButton::Unknown, true), None);
+        }
+
+        #[test]
+        fn dpad_buttons_become_one_hat() {
+            let mut dpads = DPads::default();
+            assert_eq!(dpads.button(0, GilrsButton::DPadUp, true), hat(0, HatState::Up));
+            assert_eq!(dpads.button(0, GilrsButton::DPadLeft, true), hat(0, HatState::LeftUp));
+            assert_eq!(dpads.button(1, GilrsButton::DPadDown, true), hat(1, HatState::Down));
+            assert_eq!(dpads.button(0, GilrsButton::DPadUp, false), hat(0, HatState::Left));
+            assert_eq!(dpads.button(0, GilrsButton::DPadLeft, false), hat(0, HatState::Centered));
+            assert_eq!(dpads.button(1, GilrsButton::DPadRight, true), hat(1, HatState::RightDown));
+        }
+
+        #[test]
+        fn stick_y_axes_point_down() {
+            let mut dpads = DPads::default();
+            let up = dpads.axis(0, Axis::LeftStickY, 1.0);
+            assert_eq!(up, Some(GamepadEvent::Axis(ControllerAxisArgs::new(0, 1, -1.0))));
+            let right = dpads.axis(0, Axis::RightStickX, 0.5);
+            assert_eq!(right, Some(GamepadEvent::Axis(ControllerAxisArgs::new(0, 2, 0.5))));
+            assert_eq!(dpads.axis(0, Axis::Unknown, 1.0), None);
+        }
+
+        #[test]
+        fn unplugging_forgets_the_dpad() {
+            let mut dpads = DPads::default();
+            dpads.button(0, GilrsButton::DPadUp, true);
+            assert_eq!(dpads.disconnected(0), GamepadEvent::Disconnected(0));
+            assert_eq!(dpads.button(0, GilrsButton::DPadLeft, true), hat(0, HatState::Left));
+        }
+    }
+}
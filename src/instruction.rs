@@ -0,0 +1,334 @@
+//! Decoding of 16-bit opcodes into typed instructions.
+//!
+//! The decoder knows the CHIP-8, SUPER-CHIP and XO-CHIP instruction sets;
+//! whether an instruction is available is up to the interpreter's [`Variant`].
+//! `x` and `y` are register numbers, `n`/`nn`/`nnn` the 4, 8 and 12 bit immediates.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::Variant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `00CN`: scroll the display down by N pixels.
+    ScrollDown { n: u8 },
+    /// `00DN`: scroll the display up by N pixels.
+    ScrollUp { n: u8 },
+    /// `00E0`: clear the display.
+    ClearScreen,
+    /// `00EE`: return from a subroutine.
+    Return,
+    /// `00FB`: scroll the display right by 4 pixels.
+    ScrollRight,
+    /// `00FC`: scroll the display left by 4 pixels.
+    ScrollLeft,
+    /// `00FD`: exit the interpreter.
+    Exit,
+    /// `00FE`: switch to 64x32.
+    LowRes,
+    /// `00FF`: switch to 128x64.
+    HighRes,
+    /// `1NNN`: jump to NNN.
+    Jump { nnn: u16 },
+    /// `2NNN`: call the subroutine at NNN.
+    Call { nnn: u16 },
+    /// `3XNN`: skip the next instruction if VX == NN.
+    SkipEqImm { x: u8, nn: u8 },
+    /// `4XNN`: skip the next instruction if VX != NN.
+    SkipNotEqImm { x: u8, nn: u8 },
+    /// `5XY0`: skip the next instruction if VX == VY.
+    SkipEqReg { x: u8, y: u8 },
+    /// `5XY2`: store VX to VY at I.
+    SaveRange { x: u8, y: u8 },
+    /// `5XY3`: load VX to VY from I.
+    LoadRange { x: u8, y: u8 },
+    /// `6XNN`: VX = NN.
+    LoadImm { x: u8, nn: u8 },
+    /// `7XNN`: VX += NN without carry.
+    AddImm { x: u8, nn: u8 },
+    /// `8XY0`: VX = VY.
+    Move { x: u8, y: u8 },
+    /// `8XY1`: VX |= VY.
+    Or { x: u8, y: u8 },
+    /// `8XY2`: VX &= VY.
+    And { x: u8, y: u8 },
+    /// `8XY3`: VX ^= VY.
+    Xor { x: u8, y: u8 },
+    /// `8XY4`: VX += VY, VF = carry.
+    Add { x: u8, y: u8 },
+    /// `8XY5`: VX -= VY, VF = not borrow.
+    Sub { x: u8, y: u8 },
+    /// `8XY6`: VX = VX (or VY) >> 1, VF = shifted out bit.
+    ShiftRight { x: u8, y: u8 },
+    /// `8XY7`: VX = VY - VX, VF = not borrow.
+    SubReverse { x: u8, y: u8 },
+    /// `8XYE`: VX = VX (or VY) << 1, VF = shifted out bit.
+    ShiftLeft { x: u8, y: u8 },
+    /// `9XY0`: skip the next instruction if VX != VY.
+    SkipNotEqReg { x: u8, y: u8 },
+    /// `ANNN`: I = NNN.
+    LoadIndex { nnn: u16 },
+    /// `BNNN`: jump to NNN + V0 (or XNN + VX).
+    JumpOffset { nnn: u16 },
+    /// `CXNN`: VX = random byte & NN.
+    Random { x: u8, nn: u8 },
+    /// `DXYN`: draw an 8xN sprite (16x16 if N is 0) at VX, VY.
+    Draw { x: u8, y: u8, n: u8 },
+    /// `EX9E`: skip the next instruction if the key in VX is pressed.
+    SkipKeyPressed { x: u8 },
+    /// `EXA1`: skip the next instruction if the key in VX is not pressed.
+    SkipKeyNotPressed { x: u8 },
+    /// `F000 NNNN`: I = the 16 bit address in the following word.
+    LoadIndexLong,
+    /// `FN01`: select the bit-planes N.
+    SelectPlanes { n: u8 },
+    /// `F002`: load the audio pattern at I.
+    LoadAudioPattern,
+    /// `FX07`: VX = delay timer.
+    GetDelay { x: u8 },
+    /// `FX0A`: wait for a key and store it in VX.
+    WaitKey { x: u8 },
+    /// `FX15`: delay timer = VX.
+    SetDelay { x: u8 },
+    /// `FX18`: sound timer = VX.
+    SetSound { x: u8 },
+    /// `FX1E`: I += VX.
+    AddIndex { x: u8 },
+    /// `FX29`: I = address of the small font character in VX.
+    LoadFont { x: u8 },
+    /// `FX30`: I = address of the big font character in VX.
+    LoadBigFont { x: u8 },
+    /// `FX33`: store the BCD representation of VX at I.
+    StoreBcd { x: u8 },
+    /// `FX3A`: audio pattern pitch = VX.
+    SetPitch { x: u8 },
+    /// `FX55`: store V0 to VX at I.
+    Store { x: u8 },
+    /// `FX65`: load V0 to VX from I.
+    Load { x: u8 },
+    /// `FX75`: store V0 to VX in the RPL user flags.
+    SaveFlags { x: u8 },
+    /// `FX85`: load V0 to VX from the RPL user flags.
+    LoadFlags { x: u8 },
+}
+
+/// A word that is not an instruction in any supported instruction set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub op_code: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:#06x}", self.op_code)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Decodes a single opcode.
+pub fn decode(op_code: u16) -> Result<Instruction, DecodeError> {
+    let x = ((op_code & 0x0F00) >> 8) as u8;
+    let y = ((op_code & 0x00F0) >> 4) as u8;
+    let n = (op_code & 0x000F) as u8;
+    let nn = (op_code & 0x00FF) as u8;
+    let nnn = op_code & 0x0FFF;
+    let instruction = match op_code & 0xF000 {
+        0x0000 => match op_code {
+            0x00C0..=0x00CF => Instruction::ScrollDown { n },
+            0x00D0..=0x00DF => Instruction::ScrollUp { n },
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowRes,
+            0x00FF => Instruction::HighRes,
+            _ => return Err(DecodeError { op_code }),
+        },
+        0x1000 => Instruction::Jump { nnn },
+        0x2000 => Instruction::Call { nnn },
+        0x3000 => Instruction::SkipEqImm { x, nn },
+        0x4000 => Instruction::SkipNotEqImm { x, nn },
+        0x5000 => match n {
+            0x0 => Instruction::SkipEqReg { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => return Err(DecodeError { op_code }),
+        },
+        0x6000 => Instruction::LoadImm { x, nn },
+        0x7000 => Instruction::AddImm { x, nn },
+        0x8000 => match n {
+            0x0 => Instruction::Move { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::Add { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubReverse { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => return Err(DecodeError { op_code }),
+        },
+        0x9000 if n == 0 => Instruction::SkipNotEqReg { x, y },
+        0xA000 => Instruction::LoadIndex { nnn },
+        0xB000 => Instruction::JumpOffset { nnn },
+        0xC000 => Instruction::Random { x, nn },
+        0xD000 => Instruction::Draw { x, y, n },
+        0xE000 => match nn {
+            0x9E => Instruction::SkipKeyPressed { x },
+            0xA1 => Instruction::SkipKeyNotPressed { x },
+            _ => return Err(DecodeError { op_code }),
+        },
+        0xF000 => match nn {
+            0x00 if x == 0 => Instruction::LoadIndexLong,
+            0x01 => Instruction::SelectPlanes { n: x },
+            0x02 if x == 0 => Instruction::LoadAudioPattern,
+            0x07 => Instruction::GetDelay { x },
+            0x0A => Instruction::WaitKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddIndex { x },
+            0x29 => Instruction::LoadFont { x },
+            0x30 => Instruction::LoadBigFont { x },
+            0x33 => Instruction::StoreBcd { x },
+            0x3A => Instruction::SetPitch { x },
+            0x55 => Instruction::Store { x },
+            0x65 => Instruction::Load { x },
+            0x75 => Instruction::SaveFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => return Err(DecodeError { op_code }),
+        },
+        _ => return Err(DecodeError { op_code }),
+    };
+    Ok(instruction)
+}
+
+impl Instruction {
+    /// The first instruction set that has this instruction.
+    pub fn variant(&self) -> Variant {
+        match *self {
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadBigFont { .. }
+            | Instruction::SaveFlags { .. }
+            | Instruction::LoadFlags { .. } => Variant::SuperChip,
+            Instruction::ScrollUp { .. }
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LoadIndexLong
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudioPattern
+            | Instruction::SetPitch { .. } => Variant::XoChip,
+            _ => Variant::Chip8,
+        }
+    }
+
+    /// Size of the instruction in bytes, including the address word of `F000 NNNN`.
+    pub fn size(&self) -> u16 {
+        match *self {
+            Instruction::LoadIndexLong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Returns whether this instruction conditionally skips the following one.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            *self,
+            Instruction::SkipEqImm { .. }
+                | Instruction::SkipNotEqImm { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNotEqReg { .. }
+                | Instruction::SkipKeyPressed { .. }
+                | Instruction::SkipKeyNotPressed { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_instruction() {
+        let table = [
+            (0x00C3, Instruction::ScrollDown { n: 3 }),
+            (0x00D4, Instruction::ScrollUp { n: 4 }),
+            (0x00E0, Instruction::ClearScreen),
+            (0x00EE, Instruction::Return),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FD, Instruction::Exit),
+            (0x00FE, Instruction::LowRes),
+            (0x00FF, Instruction::HighRes),
+            (0x1234, Instruction::Jump { nnn: 0x234 }),
+            (0x2345, Instruction::Call { nnn: 0x345 }),
+            (0x3A12, Instruction::SkipEqImm { x: 0xA, nn: 0x12 }),
+            (0x4B34, Instruction::SkipNotEqImm { x: 0xB, nn: 0x34 }),
+            (0x5120, Instruction::SkipEqReg { x: 1, y: 2 }),
+            (0x5122, Instruction::SaveRange { x: 1, y: 2 }),
+            (0x5123, Instruction::LoadRange { x: 1, y: 2 }),
+            (0x6C56, Instruction::LoadImm { x: 0xC, nn: 0x56 }),
+            (0x7D78, Instruction::AddImm { x: 0xD, nn: 0x78 }),
+            (0x8120, Instruction::Move { x: 1, y: 2 }),
+            (0x8121, Instruction::Or { x: 1, y: 2 }),
+            (0x8122, Instruction::And { x: 1, y: 2 }),
+            (0x8123, Instruction::Xor { x: 1, y: 2 }),
+            (0x8124, Instruction::Add { x: 1, y: 2 }),
+            (0x8125, Instruction::Sub { x: 1, y: 2 }),
+            (0x8126, Instruction::ShiftRight { x: 1, y: 2 }),
+            (0x8127, Instruction::SubReverse { x: 1, y: 2 }),
+            (0x812E, Instruction::ShiftLeft { x: 1, y: 2 }),
+            (0x9120, Instruction::SkipNotEqReg { x: 1, y: 2 }),
+            (0xA123, Instruction::LoadIndex { nnn: 0x123 }),
+            (0xB234, Instruction::JumpOffset { nnn: 0x234 }),
+            (0xC3FF, Instruction::Random { x: 3, nn: 0xFF }),
+            (0xD125, Instruction::Draw { x: 1, y: 2, n: 5 }),
+            (0xE59E, Instruction::SkipKeyPressed { x: 5 }),
+            (0xE5A1, Instruction::SkipKeyNotPressed { x: 5 }),
+            (0xF000, Instruction::LoadIndexLong),
+            (0xF201, Instruction::SelectPlanes { n: 2 }),
+            (0xF002, Instruction::LoadAudioPattern),
+            (0xF607, Instruction::GetDelay { x: 6 }),
+            (0xF60A, Instruction::WaitKey { x: 6 }),
+            (0xF615, Instruction::SetDelay { x: 6 }),
+            (0xF618, Instruction::SetSound { x: 6 }),
+            (0xF61E, Instruction::AddIndex { x: 6 }),
+            (0xF629, Instruction::LoadFont { x: 6 }),
+            (0xF630, Instruction::LoadBigFont { x: 6 }),
+            (0xF633, Instruction::StoreBcd { x: 6 }),
+            (0xF63A, Instruction::SetPitch { x: 6 }),
+            (0xF655, Instruction::Store { x: 6 }),
+            (0xF665, Instruction::Load { x: 6 }),
+            (0xF675, Instruction::SaveFlags { x: 6 }),
+            (0xF685, Instruction::LoadFlags { x: 6 }),
+        ];
+        for &(op_code, instruction) in table.iter() {
+            assert_eq!(decode(op_code), Ok(instruction), "{:04X}", op_code);
+        }
+    }
+
+    #[test]
+    fn rejects_undefined_words() {
+        for &op_code in [0x0000, 0x00E1, 0x0123, 0x5121, 0x8128, 0x812F, 0x9121, 0xE59F, 0xF100, 0xF102, 0xF6FF].iter() {
+            assert_eq!(decode(op_code), Err(DecodeError { op_code }), "{:04X}", op_code);
+        }
+    }
+
+    #[test]
+    fn variants_and_sizes() {
+        assert_eq!(decode(0x00E0).unwrap().variant(), Variant::Chip8);
+        assert_eq!(decode(0x00FF).unwrap().variant(), Variant::SuperChip);
+        assert_eq!(decode(0x00D1).unwrap().variant(), Variant::XoChip);
+        assert_eq!(decode(0xF000).unwrap().size(), 4);
+        assert_eq!(decode(0x6000).unwrap().size(), 2);
+        assert!(decode(0x3000).unwrap().is_skip());
+        assert!(!decode(0x1000).unwrap().is_skip());
+    }
+}
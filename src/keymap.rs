@@ -0,0 +1,409 @@
+//! Which host inputs press which CHIP-8 keys.
+//!
+//! The mapping starts out as the usual QWERTY layout and is changed by the
+//! `[keys]` table of the configuration file, then by the keys the ROM settings
+//! database has for the ROM, then by the ROM's own `[rom."FILENAME".keys]` table.
+//! Each entry replaces the inputs of one CHIP-8 key:
+//!
+//! ```toml
+//! [keys]
+//! 5 = ["W", "Up", "Axis1-", "Hat0Up"]
+//! 8 = ["S", "Down", "Axis1+", "Hat0Down"]
+//! 6 = ["E", "MouseLeft", "Gamepad0"]
+//!
+//! [gamepad]
+//! dead-zone = 50
+//! ```
+//!
+//! Controller inputs aren't tied to a particular controller, so one plugged in
+//! while the interpreter runs works straight away, and every controller's
+//! inputs are tracked separately so two of them don't release each other's keys.
+
+use crate::config::{self, Config, Table};
+use piston::input::{Button, ControllerAxisArgs, HatState, Key, MouseButton};
+
+/// A host button that can press a CHIP-8 key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(Key),
+    Mouse(MouseButton),
+    /// A button on any game controller, by index.
+    Gamepad(u8),
+    /// An analog stick or trigger axis of any game controller pushed past the dead zone,
+    /// towards its positive or negative end.
+    Axis { axis: u8, positive: bool },
+    /// One direction of a hat (d-pad) of any game controller; diagonals hold two directions.
+    Hat { hat: u8, direction: HatState },
+}
+
+/// The QWERTY layout: 1234/QWER/ASDF/ZXCV for 123C/456D/789E/A0BF.
+const DEFAULT_LAYOUT: [(u8, Key); 16] = [
+    (0x1, Key::D1), (0x2, Key::D2), (0x3, Key::D3), (0xC, Key::D4),
+    (0x4, Key::Q), (0x5, Key::W), (0x6, Key::E), (0xD, Key::R),
+    (0x7, Key::A), (0x8, Key::S), (0x9, Key::D), (0xE, Key::F),
+    (0xA, Key::Z), (0x0, Key::X), (0xB, Key::C), (0xF, Key::V),
+];
+
+/// Keys the interpreter itself responds to: speed, trace, overlay, pause, step, rewind, save
+/// state slots and quitting. Binding them to CHIP-8 keys would trigger both.
+const HOTKEYS: [Key; 20] = [
+    Key::Equals, Key::NumPadPlus, Key::Minus, Key::NumPadMinus, Key::T, Key::Backquote, Key::P, Key::N,
+    Key::LShift, Key::RShift, Key::Backspace, Key::Escape,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+];
+
+/// How far, in percent of the full range, an axis must move from the center to press its keys.
+pub const DEFAULT_DEAD_ZONE: i64 = 50;
+
+const HAT_DIRECTIONS: [HatState; 4] = [HatState::Up, HatState::Down, HatState::Left, HatState::Right];
+
+/// The directions of `HAT_DIRECTIONS` a hat state holds.
+fn hat_directions(state: HatState) -> &'static [HatState] {
+    match state {
+        HatState::Centered => &[],
+        HatState::Up => &[HatState::Up],
+        HatState::Down => &[HatState::Down],
+        HatState::Left => &[HatState::Left],
+        HatState::Right => &[HatState::Right],
+        HatState::RightUp => &[HatState::Right, HatState::Up],
+        HatState::RightDown => &[HatState::Right, HatState::Down],
+        HatState::LeftUp => &[HatState::Left, HatState::Up],
+        HatState::LeftDown => &[HatState::Left, HatState::Down],
+    }
+}
+
+const MOUSE_BUTTONS: [MouseButton; 8] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::X1,
+    MouseButton::X2,
+    MouseButton::Button6,
+    MouseButton::Button7,
+    MouseButton::Button8,
+];
+
+impl Input {
+    /// Looks up an input by the name used in the configuration file, ignoring case:
+    /// piston's key names such as `Q`, `Up` or `NumPad5` (digits can be written as `1`
+    /// instead of `D1`), `MouseLeft`, `MouseRight`, `MouseMiddle` and so on, `Gamepad0`
+    /// for the first button of a game controller, `Axis0+` and `Axis0-` for the two ends
+    /// of its first axis and `Hat0Up`, `Hat0Down`, `Hat0Left` and `Hat0Right` for its first hat.
+    pub fn from_name(name: &str) -> Option<Input> {
+        let lower = name.to_ascii_lowercase();
+        if let Some(index) = lower.strip_prefix("gamepad") {
+            return index.parse().ok().map(Input::Gamepad);
+        }
+        if let Some(axis) = lower.strip_prefix("axis") {
+            let positive = match axis.chars().last() {
+                Some('+') => true,
+                Some('-') => false,
+                _ => return None,
+            };
+            return axis[..axis.len() - 1].parse().ok().map(|axis| Input::Axis { axis, positive });
+        }
+        if let Some(hat) = lower.strip_prefix("hat") {
+            let digits = hat.chars().take_while(char::is_ascii_digit).count();
+            let direction = HAT_DIRECTIONS.iter()
+                .find(|direction| format!("{:?}", direction).eq_ignore_ascii_case(&hat[digits..]))?;
+            return hat[..digits].parse().ok().map(|hat| Input::Hat { hat, direction: *direction });
+        }
+        if let Some(button) = lower.strip_prefix("mouse") {
+            return MOUSE_BUTTONS.iter()
+                .find(|candidate| format!("{:?}", candidate).eq_ignore_ascii_case(button))
+                .map(|&button| Input::Mouse(button));
+        }
+        let lower = match lower.as_str() {
+            digit if digit.len() == 1 && digit.as_bytes()[0].is_ascii_digit() => format!("d{}", digit),
+            _ => lower,
+        };
+        // Piston's key codes are ASCII for printable keys and SDL's scancode-based codes for the rest.
+        (0x00..0x80).chain(0x4000_0039..0x4000_011B)
+            .map(Key::from)
+            .find(|&key| key != Key::Unknown && format!("{:?}", key).to_ascii_lowercase() == lower)
+            .map(Input::Key)
+    }
+}
+
+/// Inputs bound to each of the 16 CHIP-8 keys.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: [Vec<Input>; 16],
+    /// The part of an axis' range around the center, from 0 to 1, that presses nothing.
+    dead_zone: f64,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        let mut bindings: [Vec<Input>; 16] = Default::default();
+        for &(index, key) in DEFAULT_LAYOUT.iter() {
+            bindings[index as usize].push(Input::Key(key));
+        }
+        KeyMap { bindings, dead_zone: DEFAULT_DEAD_ZONE as f64 / 100.0 }
+    }
+}
+
+impl KeyMap {
+    /// The default layout changed by the `[keys]` table of `config`, then by `database_keys` (from
+    /// the ROM settings database) and then by the table for the ROM file named `rom_name`, if they exist.
+    /// The dead zone of controller axes is set by `dead-zone` in the `[gamepad]` table.
+    pub fn from_config(config: &Config, rom_name: &str, database_keys: Option<&Table>) -> Result<KeyMap, String> {
+        let mut key_map = KeyMap::default();
+        if let Some(table) = config.table(&["gamepad"]) {
+            for (name, value) in table {
+                match name.as_str() {
+                    "dead-zone" => {
+                        let percent = value.as_integer().filter(|percent| (0..100).contains(percent))
+                            .ok_or_else(|| String::from("[gamepad]: dead-zone expects a percentage from 0 to 99"))?;
+                        key_map.dead_zone = percent as f64 / 100.0;
+                    },
+                    _ => return Err(format!("[gamepad]: unknown setting \"{}\"", name)),
+                }
+            }
+        }
+        if let Some(table) = config.table(&["keys"]) {
+            key_map.apply(table).map_err(|e| format!("[keys]: {}", e))?;
+        }
+        if let Some(table) = database_keys {
+            key_map.apply(table).map_err(|e| format!("ROM settings database: {}", e))?;
+        }
+        if let Some(table) = config.table(&["rom", rom_name, "keys"]) {
+            key_map.apply(table).map_err(|e| format!("[rom.\"{}\".keys]: {}", rom_name, e))?;
+        }
+        Ok(key_map)
+    }
+
+    /// Replaces the inputs of every CHIP-8 key named in `table` (as a hex digit) by the listed ones.
+    /// Fails on unknown inputs and on the interpreter's own hotkeys.
+    pub fn apply(&mut self, table: &Table) -> Result<(), String> {
+        for (name, value) in table {
+            let index = u8::from_str_radix(name, 16).ok().filter(|&index| index <= 0xF)
+                .ok_or_else(|| format!("\"{}\" is not a CHIP-8 key (0-F)", name))?;
+            let names = config::strings(value)
+                .ok_or_else(|| format!("key {} expects a list of input names", name))?;
+            self.bindings[index as usize] = names.iter()
+                .map(|input| match Input::from_name(input) {
+                    Some(Input::Key(key)) if HOTKEYS.contains(&key) => {
+                        Err(format!("\"{}\" is a hotkey and can't be bound to a CHIP-8 key", input))
+                    },
+                    Some(input) => Ok(input),
+                    None => Err(format!("unknown input \"{}\"", input)),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    /// The CHIP-8 keys `input` is bound to.
+    pub fn chip8_keys(&self, input: Input) -> impl Iterator<Item = u8> + '_ {
+        (0..16u8).filter(move |&index| self.bindings[index as usize].contains(&input))
+    }
+}
+
+/// Tracks which inputs are held so a CHIP-8 key bound to several of them stays pressed
+/// until the last one is released.
+///
+/// It is driven by piston's button and axis events and returns the resulting CHIP-8 key
+/// changes, so it can be fed synthetic events as well as a window's.
+#[derive(Debug, Clone, Default)]
+pub struct Keypad {
+    key_map: KeyMap,
+    /// Held inputs with the controller they are on, `None` for the keyboard and mouse.
+    held: Vec<(Option<i32>, Input)>,
+}
+
+impl Keypad {
+    pub fn new(key_map: KeyMap) -> Keypad {
+        Keypad { key_map, held: Vec::new() }
+    }
+
+    /// Records a keyboard key, mouse button, controller button or hat going down or up, returning
+    /// the CHIP-8 keys whose state changed. A hat press holds the directions of its new state.
+    pub fn button(&mut self, button: Button, pressed: bool) -> Vec<(u8, bool)> {
+        let before: Vec<u8> = self.pressed_keys().collect();
+        match button {
+            Button::Keyboard(key) => self.set(None, Input::Key(key), pressed),
+            Button::Mouse(button) => self.set(None, Input::Mouse(button), pressed),
+            Button::Controller(button) => self.set(Some(button.id), Input::Gamepad(button.button), pressed),
+            Button::Hat(hat) => {
+                let state = if pressed { hat.state } else { HatState::Centered };
+                for &direction in HAT_DIRECTIONS.iter() {
+                    let held = hat_directions(state).contains(&direction);
+                    self.set(Some(hat.id), Input::Hat { hat: hat.which, direction }, held);
+                }
+            },
+        }
+        self.changes(&before)
+    }
+
+    /// Records a controller axis moving, returning the CHIP-8 keys whose state changed. The end
+    /// of the axis it is pushed towards is held while it is outside the dead zone.
+    pub fn axis(&mut self, args: &ControllerAxisArgs) -> Vec<(u8, bool)> {
+        let before: Vec<u8> = self.pressed_keys().collect();
+        let dead_zone = self.key_map.dead_zone;
+        self.set(Some(args.id), Input::Axis { axis: args.axis, positive: true }, args.position > dead_zone);
+        self.set(Some(args.id), Input::Axis { axis: args.axis, positive: false }, args.position < -dead_zone);
+        self.changes(&before)
+    }
+
+    /// Releases every input, e.g. when the window loses focus and release events would be missed.
+    pub fn release_all(&mut self) -> Vec<(u8, bool)> {
+        let before: Vec<u8> = self.pressed_keys().collect();
+        self.held.clear();
+        self.changes(&before)
+    }
+
+    /// Releases the inputs held on the controller with this id, e.g. when it was unplugged.
+    pub fn controller_disconnected(&mut self, id: i32) -> Vec<(u8, bool)> {
+        let before: Vec<u8> = self.pressed_keys().collect();
+        self.held.retain(|&(controller, _)| controller != Some(id));
+        self.changes(&before)
+    }
+
+    fn set(&mut self, controller: Option<i32>, input: Input, pressed: bool) {
+        self.held.retain(|&held| held != (controller, input));
+        if pressed {
+            self.held.push((controller, input));
+        }
+    }
+
+    /// The CHIP-8 keys released and pressed since `before` was pressed.
+    fn changes(&self, before: &[u8]) -> Vec<(u8, bool)> {
+        let after: Vec<u8> = self.pressed_keys().collect();
+        let released = before.iter().filter(|key| !after.contains(key)).map(|&key| (key, false));
+        let pressed = after.iter().filter(|key| !before.contains(key)).map(|&key| (key, true));
+        released.chain(pressed).collect()
+    }
+
+    fn pressed_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16u8).filter(move |&index| self.held.iter().any(|&(_, input)| self.key_map.chip8_keys(input).any(|key| key == index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Value;
+    use piston::input::{ControllerButton, ControllerHat};
+
+    fn table(entries: &[(&str, &[&str])]) -> Table {
+        entries.iter()
+            .map(|&(key, inputs)| {
+                let inputs = inputs.iter().map(|input| Value::String(input.to_string())).collect();
+                (key.to_string(), Value::Array(inputs))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binds_named_inputs() {
+        let mut key_map = KeyMap::default();
+        key_map.apply(&table(&[("5", &["up", "MouseLeft", "Gamepad3", "Axis1-", "Hat0Left"])])).unwrap();
+        assert_eq!(key_map.chip8_keys(Input::Key(Key::Up)).collect::<Vec<_>>(), [5]);
+        assert_eq!(key_map.chip8_keys(Input::Mouse(MouseButton::Left)).collect::<Vec<_>>(), [5]);
+        assert_eq!(key_map.chip8_keys(Input::Gamepad(3)).collect::<Vec<_>>(), [5]);
+        assert_eq!(key_map.chip8_keys(Input::Axis { axis: 1, positive: false }).collect::<Vec<_>>(), [5]);
+        assert_eq!(key_map.chip8_keys(Input::Hat { hat: 0, direction: HatState::Left }).collect::<Vec<_>>(), [5]);
+        assert_eq!(key_map.chip8_keys(Input::Key(Key::W)).count(), 0);
+        assert_eq!(key_map.chip8_keys(Input::Key(Key::Q)).collect::<Vec<_>>(), [4]);
+    }
+
+    #[test]
+    fn rejects_hotkeys_and_unknown_inputs() {
+        for hotkey in ["Equals", "Minus", "NumPadPlus", "NumPadMinus", "T", "Backquote", "P", "N", "LShift", "RShift",
+                       "Backspace", "Escape", "F1", "F8"] {
+            let error = KeyMap::default().apply(&table(&[("1", &["X", hotkey])])).unwrap_err();
+            assert!(error.contains("hotkey"), "{} was accepted: {}", hotkey, error);
+        }
+        assert!(KeyMap::default().apply(&table(&[("1", &["F9"])])).is_ok());
+        assert_eq!(KeyMap::default().apply(&table(&[("1", &["Nope"])])), Err(String::from("unknown input \"Nope\"")));
+        assert!(KeyMap::default().apply(&table(&[("G", &["X"])])).is_err());
+    }
+
+    fn keypad(entries: &[(&str, &[&str])]) -> Keypad {
+        let mut key_map = KeyMap::default();
+        key_map.apply(&table(entries)).unwrap();
+        Keypad::new(key_map)
+    }
+
+    fn gamepad(id: i32, button: u8) -> Button {
+        Button::Controller(ControllerButton::new(id, button))
+    }
+
+    fn hat(id: i32, state: HatState) -> Button {
+        Button::Hat(ControllerHat::new(id, 0, state))
+    }
+
+    fn axis(id: i32, axis: u8, position: f64) -> ControllerAxisArgs {
+        ControllerAxisArgs::new(id, axis, position)
+    }
+
+    #[test]
+    fn axes_press_nothing_inside_the_dead_zone() {
+        let mut keypad = keypad(&[("4", &["Axis0-"]), ("6", &["Axis0+"])]);
+        assert_eq!(keypad.axis(&axis(0, 0, 0.3)), []);
+        assert_eq!(keypad.axis(&axis(0, 0, 0.5)), []);
+        assert_eq!(keypad.axis(&axis(0, 0, 0.8)), [(6, true)]);
+        assert_eq!(keypad.axis(&axis(0, 0, 0.9)), []);
+        assert_eq!(keypad.axis(&axis(0, 0, -0.8)), [(6, false), (4, true)]);
+        assert_eq!(keypad.axis(&axis(0, 0, -0.2)), [(4, false)]);
+        assert_eq!(keypad.axis(&axis(0, 1, 1.0)), []);
+
+        let config = Config::parse("[gamepad]\ndead-zone = 10\n[keys]\n6 = \"Axis0+\"\n").unwrap();
+        let mut keypad = Keypad::new(KeyMap::from_config(&config, "", None).unwrap());
+        assert_eq!(keypad.axis(&axis(0, 0, 0.3)), [(6, true)]);
+        assert!(KeyMap::from_config(&Config::parse("[gamepad]\ndead-zone = 100\n").unwrap(), "", None).is_err());
+    }
+
+    #[test]
+    fn hat_diagonals_hold_two_directions() {
+        let mut keypad = keypad(&[("2", &["Hat0Up"]), ("8", &["Hat0Down"]), ("4", &["Hat0Left"]), ("6", &["Hat0Right"])]);
+        assert_eq!(keypad.button(hat(0, HatState::Up), true), [(2, true)]);
+        assert_eq!(keypad.button(hat(0, HatState::RightUp), true), [(6, true)]);
+        assert_eq!(keypad.button(hat(0, HatState::RightDown), true), [(2, false), (8, true)]);
+        assert_eq!(keypad.button(hat(0, HatState::Left), true), [(6, false), (8, false), (4, true)]);
+        assert_eq!(keypad.button(hat(0, HatState::Left), false), [(4, false)]);
+        assert_eq!(keypad.button(hat(0, HatState::LeftDown), true), [(4, true), (8, true)]);
+        assert_eq!(keypad.button(hat(0, HatState::Centered), true), [(4, false), (8, false)]);
+    }
+
+    #[test]
+    fn key_stays_down_until_its_last_input_is_released() {
+        let mut keypad = keypad(&[("5", &["W", "Gamepad0", "Axis1-", "Hat0Up"])]);
+        assert_eq!(keypad.button(Button::Keyboard(Key::W), true), [(5, true)]);
+        assert_eq!(keypad.button(gamepad(0, 0), true), []);
+        assert_eq!(keypad.axis(&axis(0, 1, -1.0)), []);
+        assert_eq!(keypad.button(hat(0, HatState::LeftUp), true), []);
+        assert_eq!(keypad.button(Button::Keyboard(Key::W), false), []);
+        assert_eq!(keypad.button(gamepad(0, 0), false), []);
+        assert_eq!(keypad.axis(&axis(0, 1, 0.0)), []);
+        assert_eq!(keypad.button(hat(0, HatState::Centered), false), [(5, false)]);
+        assert_eq!(keypad.button(Button::Keyboard(Key::Q), true), [(4, true)]);
+    }
+
+    #[test]
+    fn controllers_are_tracked_separately() {
+        let mut keypad = keypad(&[("5", &["Gamepad0", "Axis1-"])]);
+        assert_eq!(keypad.button(gamepad(0, 0), true), [(5, true)]);
+        assert_eq!(keypad.button(gamepad(1, 0), true), []);
+        assert_eq!(keypad.button(gamepad(0, 0), false), []);
+        assert_eq!(keypad.button(gamepad(1, 0), false), [(5, false)]);
+
+        // A centered stick on one controller doesn't release the other's.
+        assert_eq!(keypad.axis(&axis(0, 1, -1.0)), [(5, true)]);
+        assert_eq!(keypad.axis(&axis(1, 1, 0.0)), []);
+        assert_eq!(keypad.axis(&axis(1, 1, -1.0)), []);
+        assert_eq!(keypad.controller_disconnected(0), []);
+        assert_eq!(keypad.controller_disconnected(1), [(5, false)]);
+
+        assert_eq!(keypad.button(gamepad(2, 0), true), [(5, true)]);
+        assert_eq!(keypad.release_all(), [(5, false)]);
+    }
+
+    #[test]
+    fn reports_where_a_hotkey_was_bound() {
+        let config = Config::parse("[rom.\"pong.ch8\".keys]\n1 = \"P\"\n").unwrap();
+        let error = KeyMap::from_config(&config, "pong.ch8", None).unwrap_err();
+        assert!(error.starts_with("[rom.\"pong.ch8\".keys]: \"P\" is a hotkey"), "{}", error);
+    }
+}
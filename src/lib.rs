@@ -0,0 +1,37 @@
+//! A headless CHIP-8 interpreter.
+//!
+//! The core has no windowing or graphics dependencies; frontends drive it by
+//! calling [`Chip8::run_frame`] 60 times per second (or [`Chip8::emulate_cycle`]
+//! and [`Chip8::tick_timers`] on their own schedule), feeding key state
+//! through [`Chip8::set_key`] and drawing [`Chip8::framebuffer`].
+//!
+//! ```no_run
+//! let rom = std::fs::read("pong.ch8").unwrap();
+//! let mut chip8 = chip8_rust::chip8::new(&[]);
+//! chip8.load_rom(&rom, chip8_rust::chip8::PROGRAM_START).expect("not a usable ROM");
+//! chip8.run_cycles(100).expect("ROM crashed");
+//! let lit = chip8.framebuffer().iter().filter(|&&pixel| pixel == 1).count();
+//! println!("{} pixels lit, PC at {:#05x}", lit, chip8.program_counter());
+//! ```
+
+pub mod audio;
+pub mod chip8;
+pub mod config;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
+pub mod instruction;
+#[cfg(feature = "frontend")]
+pub mod keymap;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod rom;
+pub mod snapshot;
+pub mod state;
+pub mod trace;
+
+pub use crate::chip8::{Chip8, Chip8Error, Variant, DEFAULT_CYCLES_PER_FRAME, TIMER_FREQUENCY};
+pub use crate::chip8::{HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use crate::quirks::Quirks;
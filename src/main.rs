@@ -1,181 +1,262 @@
-mod chip8;
+#[cfg(feature = "frontend")]
+mod audio_output;
+mod cli;
+#[cfg(feature = "frontend")]
+mod gamepad_input;
+#[cfg(feature = "frontend")]
+mod overlay;
+#[cfg(feature = "frontend")]
+mod window;
+
 use std::env;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::process;
+
+use chip8_rust::chip8;
+use chip8_rust::database::{Database, RomSettings};
+use chip8_rust::disasm;
+use chip8_rust::instruction::decode;
+use chip8_rust::movie::{Movie, Playback};
+use chip8_rust::rom;
+use chip8_rust::snapshot::{self, ImageFormat, DEFAULT_PALETTE};
+use chip8_rust::{Variant, DEFAULT_CYCLES_PER_FRAME};
+use cli::{Command, DisasmOptions, HeadlessOptions, InfoOptions, MachineOptions};
+#[cfg(feature = "frontend")]
+use window::run_command;
 
-extern crate rand;
+#[cfg(feature = "frontend")]
 extern crate glutin_window;
+#[cfg(feature = "frontend")]
 extern crate graphics;
+#[cfg(feature = "frontend")]
 extern crate opengl_graphics;
+#[cfg(feature = "frontend")]
 extern crate piston;
 
-use glutin_window::GlutinWindow as Window;
-use opengl_graphics::{GlGraphics, OpenGL};
-use piston::event_loop::{EventSettings, Events};
-use piston::input::{RenderArgs, RenderEvent, UpdateArgs, UpdateEvent, Key};
-use piston::window::WindowSettings;
-use piston::input::*;
+fn read_rom(rom_path: &str) -> Result<Vec<u8>, String> {
+    fs::read(rom_path).map_err(|e| format!("Failed to read file \"{}\" due to: {}", rom_path, e))
+}
 
-struct Game {
-    gl: GlGraphics,
-    chip8: chip8::Chip8,
+fn read_movie(replay_path: &str) -> Result<Movie, String> {
+    fs::read_to_string(replay_path).map_err(|e| e.to_string())
+        .and_then(|text| Movie::parse(&text).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to read movie \"{}\" due to: {}", replay_path, e))
 }
 
-impl Game {
-    fn render(&mut self, args: &RenderArgs) {
-        use graphics::*;
-        const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
-        const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-        const PIXEL_SIZE: f64 = 16.0;
-
-        if self.chip8.draw {
-            self.chip8.draw = false;
-            let gfx = self.chip8.gfx;
-            self.gl.draw(args.viewport(), |c, gl| {
-              clear(BLACK, gl);
-              for y in 0..32 {
-                  for x in 0..64 {
-                      if gfx[(y * 64) + x] == 1 {
-                          let rect = rectangle::square(0.0, 0.0, PIXEL_SIZE);
-                          let transform = c
-                            .transform
-                            .trans(x as f64 * PIXEL_SIZE, y as f64 * PIXEL_SIZE);
-                          rectangle(WHITE, rect, transform, gl);
-                      }
-                  }
-              }
-            });
-        }
+/// The bundled ROM settings database extended by the user's, if there is one.
+fn load_database() -> Result<Database, String> {
+    let mut database = Database::bundled();
+    if let Some(path) = cli::user_database_path().filter(|path| path.exists()) {
+        let user = fs::read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|text| Database::parse(&text))
+            .map_err(|e| format!("Failed to read ROM database \"{}\" due to: {}", path.display(), e))?;
+        database.merge(user);
     }
-    fn update(&mut self, args: &UpdateArgs) {
-        if self.chip8.wait_keys {
-            return;
-        }
-        self.chip8.emulate_cycle();
-    }
-
-    fn key_pressed(&mut self, key: Key) {
-        self.chip8.wait_keys = false;
-        Game::update_keys(key, 1, &mut self.chip8.keys);
-    }
-
-    fn key_released(&mut self, key: Key) {
-        self.chip8.wait_keys = false;
-        Game::update_keys(key, 0, &mut self.chip8.keys);
-    }
-
-    fn update_keys(key: Key, state: u8, keys: &mut [u8; 16]) {
-        match key {
-            Key::D1 => {
-                keys[0x1] = state;
-            },
-            Key::D2 => {
-                keys[0x2] = state;
-            },
-            Key::D3 => {
-                keys[0x3] = state;
-            },
-            Key::D4 => {
-                keys[0xC] = state;
-            },
-            Key::Q => {
-                keys[0x4] = state;
-            },
-            Key::W => {
-                keys[0x5] = state;
-            },
-            Key::E => {
-                keys[0x6] = state;
-            },
-            Key::R => {
-                keys[0xD] = state;
-            }
-            Key::A => {
-                keys[0x7] = state;
-            },
-            Key::S => {
-                keys[0x8] = state;
-            },
-            Key::D => {
-                keys[0x9] = state;
-            },
-            Key::F => {
-                keys[0xE] = state;
-            },
-            Key::Z => {
-                keys[0xA] = state;
-            },
-            Key::X => {
-                keys[0x0] = state;
-            },
-            Key::C => {
-                keys[0xB] = state;
-            },
-            Key::V => {
-                keys[0xF] = state;
-            },
-            _ => {
-                // Do nothing
-            }
-        }
+    Ok(database)
+}
+
+/// The database's settings for the ROM, unless `--no-database` was given.
+fn rom_settings(options: &MachineOptions, rom_bytes: &[u8]) -> Result<RomSettings, String> {
+    if !options.use_database {
+        return Ok(RomSettings::default());
     }
+    let settings = load_database()?.lookup(rom_bytes).cloned().unwrap_or_default();
+    if let Some(title) = &settings.title {
+        eprintln!("Using the settings for {} from the ROM database", title);
+    }
+    Ok(settings)
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let rom_path = &args[1];
-    let rom_bytes = match fs::read(rom_path) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to read file \"{}\" due to: {}", rom_path, e);
-            return;
-        }
+/// Creates a machine configured by `options`, or else by `settings`, with the ROM loaded, printing any
+/// warnings about the ROM.
+fn create_chip8(options: &MachineOptions, settings: &RomSettings, rom_path: &str, rom_bytes: &[u8]) -> Result<chip8::Chip8, String> {
+    let mut chip8 = match options.seed {
+        Some(seed) => chip8::with_seed(&[], seed),
+        None => chip8::new(&[]),
     };
+    chip8.set_cycles_per_frame(options.cycles_per_frame.or(settings.cycles_per_frame).unwrap_or(DEFAULT_CYCLES_PER_FRAME));
+    chip8.set_quirks(options.quirks.or(settings.quirks).unwrap_or_default());
+    chip8.set_variant(options.variant.or(settings.variant).unwrap_or(Variant::Chip8));
+    let warnings = chip8.load_rom(rom_bytes, options.load_address)
+        .map_err(|e| format!("Failed to load \"{}\": {}", rom_path, e))?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(chip8)
+}
 
-    let chip8 = chip8::new(rom_bytes.as_slice());
-
-    let opengl = OpenGL::V3_2;
-    let mut window: Window = WindowSettings::new("chip8", [1024, 512])
-        .graphics_api(opengl)
-        .exit_on_esc(true)
-        .build()
-        .unwrap();
+/// Prints an annotated listing of a ROM.
+fn disasm_command(options: DisasmOptions) -> Result<(), String> {
+    let rom_bytes = read_rom(&options.rom_path)?;
+    print!("{}", disasm::disassemble(&rom_bytes, options.load_address, options.syntax));
+    Ok(())
+}
 
-    let mut game = Game{
-        gl: GlGraphics::new(opengl),
-        chip8: chip8,
+/// Runs a ROM without a window and dumps the display and registers, for CI.
+///
+/// The display goes to `--screenshot` (ASCII art on stdout by default) and the registers to `--registers`
+/// as JSON (`-` for stdout). Returns an error if the ROM crashed, after writing both.
+fn headless_command(options: HeadlessOptions) -> Result<(), String> {
+    let rom_bytes = read_rom(&options.rom_path)?;
+    let settings = rom_settings(&options.machine, &rom_bytes)?;
+    let mut playback = None;
+    let mut chip8 = match &options.replay_path {
+        Some(replay_path) => {
+            let movie = read_movie(replay_path)?;
+            let chip8 = movie.start(&rom_bytes).map_err(|e| format!("Failed to replay \"{}\": {}", replay_path, e))?;
+            playback = Some(Playback::new(movie));
+            chip8
+        },
+        None => {
+            // Headless runs are reproducible unless a seed says otherwise.
+            let machine = MachineOptions { seed: Some(options.machine.seed.unwrap_or(0)), ..options.machine };
+            create_chip8(&machine, &settings, &options.rom_path, &rom_bytes)?
+        },
     };
 
-    let event_settings = EventSettings{
-        max_fps: 500,
-        ups: 500,
-        ups_reset: 5,
-        swap_buffers: true,
-        bench_mode: false,
-        lazy: false,
+    let result = match options.cycles {
+        Some(cycles) => run_for_cycles(&mut chip8, playback.as_mut(), cycles as u64),
+        None => (0..options.frames).try_for_each(|_| {
+            if let Some(playback) = &mut playback {
+                playback.apply(&mut chip8);
+            }
+            chip8.run_frame()
+        }),
     };
-    let mut events = Events::new(event_settings);
-    while let Some(e) = events.next(&mut window) {
-        if let Some(args) = e.render_args() {
-            game.render(&args);
-        }
 
-        if let Some(args) = e.update_args() {
-            game.update(&args);
-        }
+    // Without a file the image goes to stdout, where only ASCII art makes sense by default.
+    let screenshot_path = options.screenshot_path.as_deref();
+    let format = options.format.unwrap_or_else(|| {
+        let extension = screenshot_path.and_then(|path| path.rsplit('.').next());
+        extension.and_then(ImageFormat::from_name).unwrap_or(ImageFormat::Ascii)
+    });
+    let (scale, palette) = (options.scale, &options.palette.or(settings.palette).unwrap_or(DEFAULT_PALETTE));
+    match screenshot_path {
+        Some(path) => File::create(path)
+            .and_then(|file| snapshot::write_framebuffer(BufWriter::new(file), &chip8, format, scale, palette))
+            .map_err(|e| format!("Failed to write screenshot \"{}\" due to: {}", path, e))?,
+        None => snapshot::write_framebuffer(io::stdout().lock(), &chip8, format, scale, palette)
+            .map_err(|e| format!("Failed to write screenshot due to: {}", e))?,
+    }
+    let json = snapshot::registers_json(&chip8, result.as_ref().err());
+    match options.registers_path.as_deref() {
+        Some("-") => println!("{}", json),
+        Some(path) => fs::write(path, json + "\n")
+            .map_err(|e| format!("Failed to write registers \"{}\" due to: {}", path, e))?,
+        None => {},
+    }
+    result.map_err(|e| format!("Execution halted: {}", e))
+}
 
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            game.key_pressed(key);
+/// Runs `cycles` instructions in whole frames, so the timers tick and a movie's keys arrive as they did
+/// when it was recorded; only the last frame is cut short. Stops early once the program exits or waits
+/// for a key that no movie is going to press.
+fn run_for_cycles(chip8: &mut chip8::Chip8, mut playback: Option<&mut Playback>, cycles: u64) -> Result<(), chip8::Chip8Error> {
+    let end = chip8.cycle_count() + cycles;
+    while chip8.cycle_count() < end && !chip8.has_exited() {
+        if let Some(playback) = playback.as_deref_mut() {
+            playback.apply(chip8);
+        }
+        if chip8.is_waiting_for_key() && playback.as_ref().is_none_or(|playback| playback.is_finished(chip8)) {
+            break;
         }
+        let remaining = end - chip8.cycle_count();
+        if remaining < u64::from(chip8.cycles_per_frame()) {
+            return chip8.run_cycles(remaining as usize);
+        }
+        chip8.run_frame()?;
+    }
+    Ok(())
+}
 
-        if let Some(button) = e.release_args() {
-            match button {
-                Button::Keyboard(key) => {
-                    game.key_released(key);
-                },
-                _ => {
-                    // Do nothing
-                }
-            }
+/// Prints the size and hash of a ROM, the instruction set its reachable code needs, its entry in the
+/// settings database and any loader warnings.
+fn info_command(options: InfoOptions) -> Result<(), String> {
+    let rom_bytes = read_rom(&options.rom_path)?;
+    let database = load_database()?;
+    let mut chip8 = chip8::with_seed(&[], 0);
+    chip8.set_variant(options.variant);
+    let warnings = chip8.load_rom(&rom_bytes, options.load_address)
+        .map_err(|e| format!("Failed to load \"{}\": {}", options.rom_path, e))?;
+
+    let code = disasm::analyze(&rom_bytes, options.load_address);
+    let start = options.load_address as usize;
+    let required = code.instructions.iter()
+        .filter_map(|&address| {
+            let offset = address as usize - start;
+            decode((rom_bytes[offset] as u16) << 8 | rom_bytes[offset + 1] as u16).ok()
+        })
+        .map(|instruction| instruction.variant())
+        .max()
+        .unwrap_or(Variant::Chip8);
+
+    println!("File:         {}", options.rom_path);
+    println!("Size:         {} bytes ({:#05x}-{:#05x})", rom_bytes.len(), start, start + rom_bytes.len() - 1);
+    println!("Instructions: {} reachable", code.instructions.len());
+    println!("SHA-1:        {}", rom::sha1_hex(&rom_bytes));
+    println!("Variant:      {}", required.name());
+    match database.lookup(&rom_bytes) {
+        Some(settings) => print_settings(settings),
+        None => println!("Database:     unknown ROM"),
+    }
+    if warnings.is_empty() {
+        println!("Warnings:     none");
+    }
+    for warning in warnings {
+        println!("Warning:      {}", warning);
+    }
+    Ok(())
+}
+
+/// Prints what the ROM settings database sets for a ROM.
+fn print_settings(settings: &RomSettings) {
+    println!("Database:     {}", settings.title.as_deref().unwrap_or("untitled"));
+    if let Some(variant) = settings.variant {
+        println!("  variant:    {}", variant.name());
+    }
+    if let Some(quirks) = settings.quirks {
+        let enabled: Vec<&str> = quirks.flags().iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect();
+        println!("  quirks:     {}", if enabled.is_empty() { String::from("none") } else { enabled.join(", ") });
+    }
+    if let Some(cycles_per_frame) = settings.cycles_per_frame {
+        println!("  speed:      {} instructions per frame", cycles_per_frame);
+    }
+    if let Some(palette) = settings.palette {
+        let colors: Vec<String> = palette.iter().map(|[r, g, b]| format!("{:02X}{:02X}{:02X}", r, g, b)).collect();
+        println!("  colours:    {}", colors.join(","));
+    }
+    if let Some(keys) = &settings.keys {
+        println!("  keys:       {} remapped", keys.len());
+    }
+}
+
+/// Without a window `run` is unavailable; `headless`, `disasm` and `info` only need the library.
+#[cfg(not(feature = "frontend"))]
+fn run_command(_options: cli::RunOptions) -> Result<(), String> {
+    Err(String::from("built without the \"frontend\" feature; use `chip8-rust headless` instead"))
+}
+
+fn main() {
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\nRun `chip8-rust --help` for usage.", e);
+            process::exit(2);
         }
+    };
+    let result = match command {
+        Command::Run(options) => run_command(options),
+        Command::Disasm(options) => disasm_command(options),
+        Command::Headless(options) => headless_command(options),
+        Command::Info(options) => info_command(options),
+        Command::Help(text) => {
+            println!("{}", text);
+            Ok(())
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
@@ -0,0 +1,336 @@
+//! Input recordings ("movies") that replay a session exactly.
+//!
+//! A movie holds everything besides the ROM that decides how a run goes: the
+//! random seed, the variant, quirks and speed, and every key change stamped with
+//! the frame ([`Chip8::frame_count`]) it happened before. Since the core runs whole
+//! frames deterministically, applying the same key changes at the same frames
+//! reproduces the session. The ROM's SHA-1 hash is stored too, so a movie isn't
+//! played back with a different ROM.
+//!
+//! Movies are stored as text, one setting or event per line:
+//!
+//! ```text
+//! chip8-movie 2
+//! rom-sha1 481353229f8e6a9f1d10a902591d896a5730adad
+//! seed 1234
+//! variant chip8
+//! cycles-per-frame 10
+//! load-address 200
+//! quirk shift_uses_vy 0
+//! length 600
+//! key 42 5 down
+//! key 50 5 up
+//! ```
+
+use std::error::Error;
+use std::fmt::{self, Write};
+
+use crate::chip8::{self, Chip8, Variant, PROGRAM_START};
+use crate::quirks::Quirks;
+use crate::rom::{self, RomError};
+
+/// Version of the movie format written by this build. Version 1 movies, which don't have the
+/// ROM's hash, are still read.
+pub const MOVIE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The frame before which the key changed.
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recorded session: the configuration at power-on followed by the key changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// SHA-1 hash of the ROM, see [`rom::sha1_hex`]; `None` in version 1 movies.
+    pub rom_sha1: Option<String>,
+    pub seed: u64,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    /// Address the ROM was loaded at.
+    pub load_address: u16,
+    /// Number of frames the recording lasted.
+    pub length: u64,
+    /// Key changes in the order they happened.
+    pub events: Vec<KeyEvent>,
+}
+
+/// A line of a movie file that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for MovieError {}
+
+/// Why a movie can't be played back with a ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The ROM isn't the one the movie was recorded with.
+    WrongRom { expected: String, found: String },
+    Rom(RomError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::WrongRom { expected, found } => {
+                write!(f, "the movie was recorded with the ROM with SHA-1 {}, not {}", expected, found)
+            },
+            ReplayError::Rom(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl Movie {
+    /// Starts a recording of `chip8`, which should have just been created, configured and loaded
+    /// with `rom_bytes`.
+    pub fn new(chip8: &Chip8, rom_bytes: &[u8]) -> Movie {
+        Movie {
+            rom_sha1: Some(rom::sha1_hex(rom_bytes)),
+            seed: chip8.seed(),
+            variant: chip8.variant(),
+            quirks: chip8.quirks(),
+            cycles_per_frame: chip8.cycles_per_frame(),
+            load_address: chip8.load_address(),
+            length: chip8.frame_count(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Creates a machine running `rom_bytes` with the recorded configuration, ready for [`Playback`].
+    /// Fails if the movie was recorded with another ROM.
+    pub fn start(&self, rom_bytes: &[u8]) -> Result<Chip8, ReplayError> {
+        if let Some(expected) = &self.rom_sha1 {
+            let found = rom::sha1_hex(rom_bytes);
+            if *expected != found {
+                return Err(ReplayError::WrongRom { expected: expected.clone(), found });
+            }
+        }
+        let mut chip8 = chip8::with_seed(&[], self.seed);
+        chip8.set_variant(self.variant);
+        chip8.set_quirks(self.quirks);
+        chip8.set_cycles_per_frame(self.cycles_per_frame);
+        chip8.load_rom(rom_bytes, self.load_address).map_err(ReplayError::Rom)?;
+        Ok(chip8)
+    }
+
+    /// Appends a key change before the current frame of `chip8`.
+    pub fn record(&mut self, chip8: &Chip8, key: u8, pressed: bool) {
+        let frame = chip8.frame_count();
+        self.events.push(KeyEvent { frame, key, pressed });
+        self.length = self.length.max(frame);
+    }
+
+    /// Drops everything recorded from `frame` on, for when the machine was rewound to a state
+    /// recorded earlier in the same session and the recording continues from there.
+    pub fn truncate(&mut self, frame: u64) {
+        self.events.retain(|event| event.frame < frame);
+        self.length = frame;
+    }
+
+    /// Marks the recording as lasting until the current frame of `chip8`.
+    pub fn finish(&mut self, chip8: &Chip8) {
+        self.length = self.length.max(chip8.frame_count());
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "chip8-movie {}", MOVIE_VERSION);
+        if let Some(rom_sha1) = &self.rom_sha1 {
+            let _ = writeln!(text, "rom-sha1 {}", rom_sha1);
+        }
+        let _ = writeln!(text, "seed {}", self.seed);
+        let _ = writeln!(text, "variant {}", self.variant.name());
+        let _ = writeln!(text, "cycles-per-frame {}", self.cycles_per_frame);
+        let _ = writeln!(text, "load-address {:X}", self.load_address);
+        for (name, enabled) in self.quirks.flags().iter() {
+            let _ = writeln!(text, "quirk {} {}", name, *enabled as u8);
+        }
+        let _ = writeln!(text, "length {}", self.length);
+        for event in &self.events {
+            let _ = writeln!(text, "key {} {:X} {}", event.frame, event.key, if event.pressed { "down" } else { "up" });
+        }
+        text
+    }
+
+    /// Parses a movie written by [`Movie::to_text`]. Settings missing from the file keep their defaults.
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie {
+            rom_sha1: None,
+            seed: 0,
+            variant: Variant::Chip8,
+            quirks: Quirks::default(),
+            cycles_per_frame: crate::chip8::DEFAULT_CYCLES_PER_FRAME,
+            load_address: PROGRAM_START,
+            length: 0,
+            events: Vec::new(),
+        };
+        let mut lines = text.lines().enumerate().map(|(number, line)| (number + 1, line.trim()));
+        match lines.next() {
+            Some((_, header)) if header == format!("chip8-movie {}", MOVIE_VERSION) || header == "chip8-movie 1" => {},
+            Some((_, header)) if header.starts_with("chip8-movie ") => {
+                return Err(MovieError { line: 1, message: format!("unsupported movie version in \"{}\"", header) });
+            },
+            _ => return Err(MovieError { line: 1, message: String::from("not a movie file") }),
+        }
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let error = |message: &str| MovieError { line, message: format!("{} in \"{}\"", message, text) };
+            let words: Vec<&str> = text.split_whitespace().collect();
+            match words.as_slice() {
+                ["rom-sha1", hash] if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    movie.rom_sha1 = Some(hash.to_ascii_lowercase())
+                },
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| error("invalid seed"))?,
+                ["variant", name] => movie.variant = Variant::from_name(name).ok_or_else(|| error("unknown variant"))?,
+                ["cycles-per-frame", cycles] => {
+                    movie.cycles_per_frame = cycles.parse().map_err(|_| error("invalid speed"))?
+                },
+                ["load-address", address] => {
+                    movie.load_address = u16::from_str_radix(address, 16).map_err(|_| error("invalid address"))?
+                },
+                ["quirk", name, value] => {
+                    let enabled = match *value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(error("invalid quirk value")),
+                    };
+                    if !movie.quirks.set_flag(name, enabled) {
+                        return Err(error("unknown quirk"));
+                    }
+                },
+                ["length", length] => movie.length = length.parse().map_err(|_| error("invalid length"))?,
+                ["key", frame, key, state] => {
+                    let frame = frame.parse().map_err(|_| error("invalid frame"))?;
+                    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key <= 0xF)
+                        .ok_or_else(|| error("invalid key"))?;
+                    let pressed = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error("key state must be down or up")),
+                    };
+                    if movie.events.last().is_some_and(|last| last.frame > frame) {
+                        return Err(error("events out of order"));
+                    }
+                    movie.events.push(KeyEvent { frame, key, pressed });
+                },
+                _ => return Err(error("unknown setting")),
+            }
+        }
+        Ok(movie)
+    }
+}
+
+/// Feeds the key changes of a movie into a machine created by [`Movie::start`].
+#[derive(Debug)]
+pub struct Playback {
+    movie: Movie,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Playback {
+        Playback { movie, next: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Applies the key changes due before the current frame. Call it before every
+    /// [`Chip8::run_frame`]; calling it again for the same frame does nothing.
+    pub fn apply(&mut self, chip8: &mut Chip8) {
+        let frame = chip8.frame_count();
+        while let Some(event) = self.movie.events.get(self.next).filter(|event| event.frame <= frame) {
+            chip8.set_key(event.key, event.pressed);
+            self.next += 1;
+        }
+    }
+
+    /// Whether the machine has run every recorded frame.
+    pub fn is_finished(&self, chip8: &Chip8) -> bool {
+        chip8.frame_count() >= self.movie.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits for a key, then mixes it with a random number, forever.
+    const ROM: [u8; 8] = [
+        0xF1, 0x0A, // V1 := key
+        0xC2, 0xFF, // V2 := random
+        0x82, 0x14, // V2 += V1
+        0x12, 0x00, // jump 0x200
+    ];
+
+    /// Plays `frames` frames pressing and releasing keys, recording them to a movie.
+    fn record_session(frames: u64) -> (Movie, Chip8) {
+        let mut chip8 = chip8::with_seed(&[], 9);
+        chip8.load_rom(&ROM, PROGRAM_START).unwrap();
+        let mut movie = Movie::new(&chip8, &ROM);
+        let presses = [(5, 0x3, true), (8, 0x3, false), (20, 0xA, true), (21, 0xA, false)];
+        while chip8.frame_count() < frames {
+            let frame = chip8.frame_count();
+            for &(_, key, pressed) in presses.iter().filter(|&&(at, _, _)| at == frame) {
+                chip8.set_key(key, pressed);
+                movie.record(&chip8, key, pressed);
+            }
+            chip8.run_frame().unwrap();
+        }
+        movie.finish(&chip8);
+        (movie, chip8)
+    }
+
+    #[test]
+    fn replay_reproduces_the_session() {
+        let (movie, recorded) = record_session(30);
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+        let mut chip8 = movie.start(&ROM).unwrap();
+        let mut playback = Playback::new(movie);
+        while !playback.is_finished(&chip8) {
+            playback.apply(&mut chip8);
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.save_state(), recorded.save_state());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let (movie, _) = record_session(30);
+        assert_eq!(movie.events.len(), 4);
+        assert_eq!(movie.rom_sha1, Some(rom::sha1_hex(&ROM)));
+        assert_eq!(Movie::parse(&movie.to_text()), Ok(movie));
+    }
+
+    #[test]
+    fn rejects_another_rom() {
+        let (movie, _) = record_session(1);
+        let mut other = ROM;
+        other[1] = 0x0B;
+        assert!(matches!(movie.start(&other), Err(ReplayError::WrongRom { .. })));
+    }
+
+    #[test]
+    fn reads_version_1_movies_without_a_hash() {
+        let movie = Movie::parse("chip8-movie 1\nseed 5\nlength 10\nkey 3 5 down\n").unwrap();
+        assert_eq!(movie.rom_sha1, None);
+        assert_eq!(movie.events, vec![KeyEvent { frame: 3, key: 5, pressed: true }]);
+        assert!(movie.start(&ROM).is_ok());
+        assert!(Movie::parse("chip8-movie 3\n").is_err());
+    }
+}
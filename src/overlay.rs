@@ -0,0 +1,161 @@
+//! The debugger panel drawn beside the game view, with its own tiny bitmap font so no font file is needed.
+
+use chip8_rust::chip8::Chip8;
+use chip8_rust::disasm::{self, Syntax};
+use graphics::math::Matrix2d;
+use graphics::{rectangle, Graphics, Transformed};
+
+/// Width of the panel in pixels.
+pub const PANEL_WIDTH: f64 = 400.0;
+
+const BACKGROUND: [f32; 4] = [0.1, 0.1, 0.15, 1.0];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const HIGHLIGHT: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+/// Size of a font pixel in window pixels.
+const SCALE: f64 = 2.0;
+/// Glyphs are 3x5 font pixels with one pixel of spacing each way.
+const CHAR_WIDTH: f64 = 4.0 * SCALE;
+const LINE_HEIGHT: f64 = 7.0 * SCALE;
+const MARGIN: f64 = 8.0;
+/// Number of instructions listed before the one at the program counter.
+const DISASSEMBLY_CONTEXT: usize = 6;
+
+/// Rows of a 3x5 glyph, most significant of the three bits on the left. Letters are upper case only.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 2, 2, 2],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 2],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'O' => [2, 5, 5, 5, 2],
+        'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 6, 3],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        ' ' => [0, 0, 0, 0, 0],
+        ':' => [0, 2, 0, 2, 0],
+        '=' => [0, 7, 0, 7, 0],
+        '+' => [0, 2, 7, 2, 0],
+        '-' => [0, 0, 7, 0, 0],
+        '<' => [1, 2, 4, 2, 1],
+        '>' => [4, 2, 1, 2, 4],
+        '&' => [2, 5, 2, 5, 3],
+        '|' => [2, 2, 2, 2, 2],
+        '^' => [2, 5, 0, 0, 0],
+        '#' => [5, 7, 5, 7, 5],
+        ',' => [0, 0, 0, 2, 4],
+        '.' => [0, 0, 0, 0, 2],
+        '[' => [6, 4, 4, 4, 6],
+        ']' => [3, 1, 1, 1, 3],
+        '(' => [1, 2, 2, 2, 1],
+        ')' => [4, 2, 2, 2, 4],
+        '!' => [2, 2, 2, 0, 2],
+        '/' => [1, 1, 2, 4, 4],
+        '_' => [0, 0, 0, 0, 7],
+        _ => [7, 1, 2, 0, 2],
+    }
+}
+
+/// The text of the panel: state, registers, stack, keys and the disassembly around the program counter.
+/// Lines starting with `>` are highlighted.
+pub fn panel_lines(chip8: &Chip8, running: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let state = if chip8.has_exited() {
+        "EXITED"
+    } else if chip8.is_waiting_for_key() {
+        "WAITING FOR KEY"
+    } else if running {
+        "RUNNING"
+    } else {
+        "PAUSED"
+    };
+    lines.push(format!("{}  CYCLE {}", state, chip8.cycle_count()));
+    lines.push(format!("PC {:04X}  I {:04X}  SP {:X}", chip8.program_counter(), chip8.index(), chip8.stack().len()));
+    lines.push(format!("DT {:02X}  ST {:02X}", chip8.delay_timer(), chip8.sound_timer()));
+    lines.push(String::new());
+    for (row, values) in chip8.registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values.iter().enumerate()
+            .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+            .collect();
+        lines.push(registers.join("  "));
+    }
+    lines.push(String::new());
+    let stack: Vec<String> = chip8.stack().iter().map(|address| format!("{:04X}", address)).collect();
+    lines.push(format!("STACK {}", if stack.is_empty() { String::from("-") } else { stack.join(" ") }));
+    let keys: Vec<String> = (0..16).filter(|&key| chip8.is_key_pressed(key)).map(|key| format!("{:X}", key)).collect();
+    lines.push(format!("KEYS {}", if keys.is_empty() { String::from("-") } else { keys.join(" ") }));
+    lines.push(String::new());
+    lines.extend(disassembly(chip8));
+    lines.push(String::new());
+    lines.push(String::from("P PAUSE/RESUME  N STEP  ` HIDE"));
+    lines
+}
+
+/// A linear sweep starting a few instructions before the program counter.
+fn disassembly(chip8: &Chip8) -> Vec<String> {
+    let pc = chip8.program_counter() as usize;
+    let mut address = pc.saturating_sub(DISASSEMBLY_CONTEXT * 2);
+    let mut lines = Vec::new();
+    while lines.len() < DISASSEMBLY_CONTEXT * 3 {
+        let (text, size) = match disasm::format_at(chip8.memory(), address, Syntax::Octo) {
+            Some(formatted) => formatted,
+            None => break,
+        };
+        let marker = if address == pc { '>' } else { ' ' };
+        lines.push(format!("{} {:04X}  {}", marker, address, text));
+        // Data before the program counter can swallow it, so resynchronise on it.
+        address = if address < pc && address + size > pc { pc } else { address + size };
+    }
+    lines
+}
+
+/// Fills a `PANEL_WIDTH` by `height` panel at `transform` with `lines`.
+pub fn draw_panel<G: Graphics>(lines: &[String], height: f64, transform: Matrix2d, gl: &mut G) {
+    rectangle(BACKGROUND, [0.0, 0.0, PANEL_WIDTH, height], transform, gl);
+    for (row, line) in lines.iter().enumerate() {
+        let color = if line.starts_with('>') { HIGHLIGHT } else { TEXT };
+        let y = MARGIN + row as f64 * LINE_HEIGHT;
+        draw_text(line, color, transform.trans(MARGIN, y), gl);
+    }
+}
+
+fn draw_text<G: Graphics>(text: &str, color: [f32; 4], transform: Matrix2d, gl: &mut G) {
+    let max_chars = ((PANEL_WIDTH - 2.0 * MARGIN) / CHAR_WIDTH) as usize;
+    for (column, c) in text.chars().take(max_chars).enumerate() {
+        for (y, bits) in glyph(c).iter().enumerate() {
+            for x in 0..3 {
+                if bits & (0b100 >> x) != 0 {
+                    let pixel = [(column as f64 * CHAR_WIDTH) + x as f64 * SCALE, y as f64 * SCALE, SCALE, SCALE];
+                    rectangle(color, pixel, transform, gl);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Behaviours that differ between CHIP-8 interpreters.
+//!
+//! ROMs are usually written against one particular interpreter and rely on its
+//! interpretation of the ambiguous opcodes, so the behaviour is configurable
+//! per ROM. Each flag documents what happens when it is `true`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY and store the result in VX, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// `FX55`/`FX65` leave I pointing past the last register stored or loaded (I += X + 1).
+    pub load_store_increments_index: bool,
+    /// Together with `load_store_increments_index`, `FX55`/`FX65` increase I by X only, pointing at
+    /// the last register rather than past it, as CHIP-48 does.
+    pub load_store_index_off_by_one: bool,
+    /// `BNNN` is decoded as `BXNN` and jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// `DXYN` wraps sprite pixels past the right edge around to the left edge instead of clipping them.
+    pub wrap_sprites_x: bool,
+    /// `DXYN` wraps sprite pixels past the bottom edge around to the top edge instead of clipping them.
+    pub wrap_sprites_y: bool,
+    /// `8XY1`/`8XY2`/`8XY3` set VF to 0.
+    pub logic_resets_vf: bool,
+    /// `FX0A` completes when the key is released rather than when it goes down.
+    pub wait_for_key_release: bool,
+    /// `DXYN` waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_index: true,
+            load_store_index_off_by_one: false,
+            jump_uses_vx: false,
+            wrap_sprites_x: false,
+            wrap_sprites_y: false,
+            logic_resets_vf: true,
+            wait_for_key_release: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators. Like SUPER-CHIP, except that `FX55`/`FX65` increase I by X,
+    /// which SUPER-CHIP 1.1 dropped.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: true,
+            load_store_index_off_by_one: true,
+            jump_uses_vx: true,
+            wrap_sprites_x: false,
+            wrap_sprites_y: false,
+            logic_resets_vf: false,
+            wait_for_key_release: false,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP-48 calculators.
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: false,
+            load_store_index_off_by_one: false,
+            jump_uses_vx: true,
+            wrap_sprites_x: false,
+            wrap_sprites_y: false,
+            logic_resets_vf: false,
+            wait_for_key_release: false,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_index: true,
+            load_store_index_off_by_one: false,
+            jump_uses_vx: false,
+            wrap_sprites_x: true,
+            wrap_sprites_y: true,
+            logic_resets_vf: false,
+            wait_for_key_release: false,
+            display_wait: false,
+        }
+    }
+
+    /// What most modern interpreters do, and what ROMs written for them expect.
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: false,
+            load_store_index_off_by_one: false,
+            jump_uses_vx: false,
+            wrap_sprites_x: true,
+            wrap_sprites_y: true,
+            logic_resets_vf: false,
+            wait_for_key_release: false,
+            display_wait: false,
+        }
+    }
+
+    /// Every flag with its field name, in declaration order.
+    pub fn flags(&self) -> [(&'static str, bool); 9] {
+        [
+            ("shift_uses_vy", self.shift_uses_vy),
+            ("load_store_increments_index", self.load_store_increments_index),
+            ("load_store_index_off_by_one", self.load_store_index_off_by_one),
+            ("jump_uses_vx", self.jump_uses_vx),
+            ("wrap_sprites_x", self.wrap_sprites_x),
+            ("wrap_sprites_y", self.wrap_sprites_y),
+            ("logic_resets_vf", self.logic_resets_vf),
+            ("wait_for_key_release", self.wait_for_key_release),
+            ("display_wait", self.display_wait),
+        ]
+    }
+
+    /// Sets the flag with the field name `name`, returning false if there is no such flag.
+    pub fn set_flag(&mut self, name: &str, value: bool) -> bool {
+        let flag = match name {
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "load_store_increments_index" => &mut self.load_store_increments_index,
+            "load_store_index_off_by_one" => &mut self.load_store_index_off_by_one,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "wrap_sprites_x" => &mut self.wrap_sprites_x,
+            "wrap_sprites_y" => &mut self.wrap_sprites_y,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            "wait_for_key_release" => &mut self.wait_for_key_release,
+            "display_wait" => &mut self.display_wait,
+            _ => return false,
+        };
+        *flag = value;
+        true
+    }
+
+    /// Looks up a preset by name: `vip`, `chip48`, `schip`, `xochip` or `modern`.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::superchip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
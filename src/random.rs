@@ -0,0 +1,83 @@
+//! The random number generator behind `CXNN`.
+//!
+//! Each [`Chip8`](crate::chip8::Chip8) owns its generator, so a run started from the
+//! same seed (or restored from a save state) produces the same numbers every time.
+
+/// A SplitMix64 generator: tiny, fast and its whole state is a single `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// The internal state, which continues the sequence when passed to [`Random::new`].
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::with_seed;
+
+    #[test]
+    fn matches_the_splitmix64_reference() {
+        let mut random = Random::new(0);
+        assert_eq!(random.next_u64(), 0xE220_A839_7B1D_CDAF);
+    }
+
+    #[test]
+    fn one_seed_always_gives_the_same_sequence() {
+        let sequence = |seed| {
+            let mut random = Random::new(seed);
+            (0..32).map(|_| random.next_byte()).collect::<Vec<u8>>()
+        };
+        assert_eq!(sequence(1234), sequence(1234));
+        assert_ne!(sequence(1234), sequence(1235));
+
+        // The state continues the sequence where it left off.
+        let mut random = Random::new(1234);
+        let first: Vec<u8> = (0..16).map(|_| random.next_byte()).collect();
+        let mut resumed = Random::new(random.state());
+        let rest: Vec<u8> = (0..16).map(|_| resumed.next_byte()).collect();
+        assert_eq!([first, rest].concat(), sequence(1234));
+    }
+
+    #[test]
+    fn machines_with_one_seed_draw_the_same_numbers() {
+        let program = [
+            0xC0, 0xFF, // v0 := random 0xff
+            0xC1, 0x0F, // v1 := random 0x0f
+            0x12, 0x00, // jump 0x200
+        ];
+        let draws = |seed| {
+            let mut chip8 = with_seed(&program, seed);
+            (0..20)
+                .map(|_| {
+                    chip8.run_cycles(3).unwrap();
+                    (chip8.registers()[0], chip8.registers()[1])
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draws(99), draws(99));
+        assert_ne!(draws(99), draws(100));
+        assert!(draws(99).iter().all(|&(_, masked)| masked <= 0x0F));
+    }
+}
@@ -0,0 +1,241 @@
+//! A ring buffer of recent machine states for running the emulation backwards.
+//!
+//! Only the newest state is kept whole. Each older one is stored as the run-length
+//! encoded XOR against its successor, which is a few dozen bytes for a typical frame
+//! since most of memory and the display don't change.
+
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+
+/// How to get from one state to the one recorded before it.
+#[derive(Debug)]
+enum Delta {
+    /// Runs of unchanged bytes and XOR masks for the changed ones, see [`encode`].
+    Xor(Vec<u8>),
+    /// The states differ in length (the variant changed), so the older one is stored whole.
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    fn len(&self) -> usize {
+        match self {
+            Delta::Xor(bytes) | Delta::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// The last `capacity` states passed to [`Rewind::record`].
+#[derive(Debug)]
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` states, e.g. 30 seconds at one state per frame is `30 * TIMER_FREQUENCY`.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind { capacity, latest: None, deltas: VecDeque::new() }
+    }
+
+    /// Number of states [`Rewind::step_back`] can go back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Approximate number of bytes used by the stored states.
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Delta::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the current state of `chip8`, dropping the oldest one when the buffer is full.
+    pub fn record(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+        let state = chip8.save_state();
+        if let Some(previous) = self.latest.replace(state) {
+            let current = self.latest.as_ref().expect("just stored");
+            let delta = if previous.len() == current.len() {
+                Delta::Xor(encode(&previous, current))
+            } else {
+                Delta::Full(previous)
+            };
+            self.deltas.push_back(delta);
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    /// Restores `chip8` to the state recorded before the newest one, which is discarded.
+    /// Returns false when there is nothing left to go back to.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let (latest, delta) = match (self.latest.as_mut(), self.deltas.pop_back()) {
+            (Some(latest), Some(delta)) => (latest, delta),
+            _ => return false,
+        };
+        match delta {
+            Delta::Xor(runs) => apply(latest, &runs),
+            Delta::Full(state) => *latest = state,
+        }
+        chip8.load_state(latest).expect("rewind states are written by the same build");
+        true
+    }
+}
+
+/// Encodes `older XOR newer` as alternating varint-prefixed runs: a count of unchanged bytes,
+/// then a count of changed bytes followed by their XOR masks.
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut position = 0;
+    while position < newer.len() {
+        let unchanged = older[position..].iter().zip(&newer[position..]).take_while(|(a, b)| a == b).count();
+        position += unchanged;
+        let changed = older[position..].iter().zip(&newer[position..]).take_while(|(a, b)| a != b).count();
+        write_varint(&mut runs, unchanged);
+        write_varint(&mut runs, changed);
+        runs.extend(older[position..position + changed].iter().zip(&newer[position..]).map(|(a, b)| a ^ b));
+        position += changed;
+    }
+    runs
+}
+
+/// Turns the newer state into the older one by applying the masks from [`encode`].
+fn apply(state: &mut [u8], runs: &[u8]) {
+    let mut runs = runs.iter().copied();
+    let mut position = 0;
+    while let Some(unchanged) = read_varint(&mut runs) {
+        position += unchanged;
+        let changed = read_varint(&mut runs).unwrap_or(0);
+        for (byte, mask) in state[position..position + changed].iter_mut().zip(&mut runs) {
+            *byte ^= mask;
+        }
+        position += changed;
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{self, Variant};
+
+    /// A machine whose registers, timers and random state change every frame.
+    fn counting_machine() -> Chip8 {
+        let program = [
+            0x70, 0x01, // V0 += 1
+            0xF0, 0x15, // delay := V0
+            0xC1, 0xFF, // V1 := random
+            0x12, 0x00, // jump 0x200
+        ];
+        chip8::with_seed(&program, 3)
+    }
+
+    /// Records the machine after each of `frames` frames, returning the states recorded.
+    fn record_frames(rewind: &mut Rewind, chip8: &mut Chip8, frames: usize) -> Vec<Vec<u8>> {
+        (0..frames).map(|_| {
+            chip8.run_frame().unwrap();
+            rewind.record(chip8);
+            chip8.save_state()
+        }).collect()
+    }
+
+    #[test]
+    fn steps_back_through_every_recorded_state() {
+        let mut rewind = Rewind::new(100);
+        let mut chip8 = counting_machine();
+        let states = record_frames(&mut rewind, &mut chip8, 20);
+        assert_eq!(rewind.len(), 19);
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewind.step_back(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewind.step_back(&mut chip8));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn steps_back_across_a_variant_change() {
+        let mut rewind = Rewind::new(100);
+        let mut chip8 = counting_machine();
+        let mut states = record_frames(&mut rewind, &mut chip8, 3);
+        // XO-CHIP's larger memory changes the length of the state.
+        chip8.set_variant(Variant::XoChip);
+        states.extend(record_frames(&mut rewind, &mut chip8, 3));
+        assert!(rewind.deltas.iter().any(|delta| matches!(delta, Delta::Full(_))));
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewind.step_back(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert_eq!(chip8.variant(), Variant::Chip8);
+    }
+
+    #[test]
+    fn drops_the_oldest_states_when_full() {
+        let mut rewind = Rewind::new(3);
+        let mut chip8 = counting_machine();
+        let states = record_frames(&mut rewind, &mut chip8, 8);
+        assert_eq!(rewind.len(), 3);
+        for expected in states[4..7].iter().rev() {
+            assert!(rewind.step_back(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewind.step_back(&mut chip8));
+    }
+
+    #[test]
+    fn nothing_is_kept_without_capacity() {
+        let mut rewind = Rewind::new(0);
+        let mut chip8 = counting_machine();
+        record_frames(&mut rewind, &mut chip8, 3);
+        assert_eq!(rewind.memory_usage(), 0);
+        assert!(!rewind.step_back(&mut chip8));
+    }
+
+    #[test]
+    fn encode_and_apply_round_trip_long_runs() {
+        // Runs longer than 127 bytes need multi-byte varints.
+        let older: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut newer = older.clone();
+        for position in (300..600).chain(999..1000) {
+            newer[position] = newer[position].wrapping_add(1);
+        }
+        newer[0] ^= 0xFF;
+        let runs = encode(&older, &newer);
+        let mut state = newer.clone();
+        apply(&mut state, &runs);
+        assert_eq!(state, older);
+        assert_eq!(encode(&older, &older), vec![232, 7, 0]);
+    }
+}
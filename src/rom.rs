@@ -0,0 +1,200 @@
+//! Checks run on a ROM before [`Chip8::load_rom`](crate::chip8::Chip8::load_rom) copies it into memory.
+//!
+//! Errors are ROMs that can't be loaded at all. Warnings are things that don't
+//! stop loading but usually mean the file isn't a CHIP-8 program, such as an
+//! image or an Octo source file passed by mistake.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::Variant;
+use crate::instruction::decode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomError {
+    /// The file holds no bytes.
+    Empty,
+    /// The ROM doesn't fit between the load address and the end of memory.
+    TooLarge { size: usize, max: usize },
+    /// The load address is outside of memory.
+    InvalidAddress { address: u16 },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but only {} fit in memory", size, max)
+            },
+            RomError::InvalidAddress { address } => {
+                write!(f, "load address {:#05x} is outside of memory", address)
+            },
+        }
+    }
+}
+
+impl Error for RomError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomWarning {
+    /// Instructions are two bytes long. Plenty of ROMs end in an odd-sized data table,
+    /// so this alone doesn't mean much.
+    OddLength { size: usize },
+    /// The file starts with the signature of another file format.
+    KnownFormat { name: &'static str },
+    /// Every byte is printable text, e.g. assembler source rather than an assembled program.
+    Text,
+    /// The first instruction isn't one the variant can execute.
+    UnknownFirstInstruction { op_code: u16 },
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomWarning::OddLength { size } => write!(f, "ROM has an odd length of {} bytes", size),
+            RomWarning::KnownFormat { name } => write!(f, "file looks like {}, not a CHIP-8 program", name),
+            RomWarning::Text => write!(f, "file is plain text, not an assembled CHIP-8 program"),
+            RomWarning::UnknownFirstInstruction { op_code } => {
+                write!(f, "ROM starts with unknown opcode {:#06x}", op_code)
+            },
+        }
+    }
+}
+
+/// Signatures of file formats that are commonly mistaken for ROMs.
+const SIGNATURES: [(&[u8], &str); 8] = [
+    (b"\x89PNG", "a PNG image"),
+    (b"PK\x03\x04", "a ZIP archive"),
+    (b"\x1f\x8b", "a gzip archive"),
+    (b"\x7fELF", "an ELF executable"),
+    (b"MZ", "a Windows executable"),
+    (b"%PDF", "a PDF document"),
+    (b"C8ST", "a save state"),
+    (b"chip8-movie", "an input recording"),
+];
+
+/// Validates `rom_bytes` for loading at `address` into `memory_size` bytes of memory
+/// interpreted as `variant`, returning the warnings for a ROM that can be loaded.
+pub fn check(rom_bytes: &[u8], address: u16, memory_size: usize, variant: Variant) -> Result<Vec<RomWarning>, RomError> {
+    let address = address as usize;
+    if address >= memory_size {
+        return Err(RomError::InvalidAddress { address: address as u16 });
+    }
+    if rom_bytes.is_empty() {
+        return Err(RomError::Empty);
+    }
+    if rom_bytes.len() > memory_size - address {
+        return Err(RomError::TooLarge { size: rom_bytes.len(), max: memory_size - address });
+    }
+
+    let mut warnings = Vec::new();
+    if !rom_bytes.len().is_multiple_of(2) {
+        warnings.push(RomWarning::OddLength { size: rom_bytes.len() });
+    }
+    if let Some(&(_, name)) = SIGNATURES.iter().find(|(signature, _)| rom_bytes.starts_with(signature)) {
+        warnings.push(RomWarning::KnownFormat { name });
+    } else if rom_bytes.iter().all(|&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace()) {
+        warnings.push(RomWarning::Text);
+    } else if rom_bytes.len() >= 2 {
+        let op_code = (rom_bytes[0] as u16) << 8 | rom_bytes[1] as u16;
+        if decode(op_code).map_or(true, |instruction| instruction.variant() > variant) {
+            warnings.push(RomWarning::UnknownFirstInstruction { op_code });
+        }
+    }
+    Ok(warnings)
+}
+
+/// The SHA-1 hash of `rom_bytes` as 40 lowercase hex digits, which identifies a ROM in the
+/// [settings database](crate::database).
+pub fn sha1_hex(rom_bytes: &[u8]) -> String {
+    sha1(rom_bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    // Padding: a 1 bit, zeros up to 56 bytes into the last block, then the length in bits.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut hash = [0; 20];
+    for (chunk, value) in hash.chunks_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_roms_that_cannot_load() {
+        assert_eq!(check(&[], 0x200, 4096, Variant::Chip8), Err(RomError::Empty));
+        assert_eq!(check(&[0; 3586], 0x200, 4096, Variant::Chip8), Err(RomError::TooLarge { size: 3586, max: 3584 }));
+        assert_eq!(check(&[0x00, 0xE0], 0x1000, 4096, Variant::Chip8), Err(RomError::InvalidAddress { address: 0x1000 }));
+        assert_eq!(check(&[0x00, 0xE0], 0x200, 4096, Variant::Chip8), Ok(vec![]));
+    }
+
+    #[test]
+    fn warns_about_files_that_are_not_programs() {
+        assert_eq!(check(b"\x89PNG\r\n\x1a\n", 0x200, 4096, Variant::Chip8), Ok(vec![RomWarning::KnownFormat { name: "a PNG image" }]));
+        assert_eq!(check(b"v0 := 5\n", 0x200, 4096, Variant::Chip8), Ok(vec![RomWarning::Text]));
+        assert_eq!(
+            check(&[0x00, 0xFF], 0x200, 4096, Variant::Chip8),
+            Ok(vec![RomWarning::UnknownFirstInstruction { op_code: 0x00FF }])
+        );
+        assert_eq!(check(&[0x00, 0xFF], 0x200, 4096, Variant::SuperChip), Ok(vec![]));
+    }
+
+    #[test]
+    fn loads_roms_with_an_odd_length() {
+        // A program followed by a one-byte data table.
+        assert_eq!(check(&[0x00, 0xE0, 0x12], 0x200, 4096, Variant::Chip8), Ok(vec![RomWarning::OddLength { size: 3 }]));
+        assert_eq!(check(&[0x00], 0x200, 4096, Variant::Chip8), Ok(vec![RomWarning::OddLength { size: 1 }]));
+    }
+
+    #[test]
+    fn sha1_matches_the_fips_test_vectors() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // 56 bytes, so the padding needs a second block.
+        assert_eq!(
+            sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(sha1_hex(&[b'a'; 1_000_000]), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+    }
+}
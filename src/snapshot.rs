@@ -0,0 +1,266 @@
+//! Dumps of the display and registers, for headless runs and tests.
+
+use std::io::{self, Write};
+
+use crate::chip8::{Chip8, Chip8Error};
+use crate::trace::json_escape;
+
+/// RGB colours indexed by pixel value; the last two only appear with XO-CHIP's second bit-plane.
+pub type Palette = [[u8; 3]; 4];
+
+/// Black and white, with orange and brown for XO-CHIP's extra colours.
+pub const DEFAULT_PALETTE: Palette = [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xFF, 0x66, 0x00], [0x66, 0x21, 0x00]];
+/// Characters used for the four pixel values in ASCII art.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '*'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// An indexed-colour PNG using the window's palette.
+    Png,
+    /// A plain (P1) portable bitmap; any lit pixel is 1.
+    Pbm,
+    /// One line of text per row, `.` for unlit and `#` for lit pixels (`+` and `*` for XO-CHIP's other colours).
+    Ascii,
+}
+
+/// Parses a colour written as six hex digits, optionally preceded by `#`, e.g. `FF6600`.
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.trim().trim_start_matches('#');
+    let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+impl ImageFormat {
+    /// Parses a format name as used on the command line: `png`, `pbm` or `ascii`.
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            "ascii" | "txt" => Some(ImageFormat::Ascii),
+            _ => None
+        }
+    }
+}
+
+/// Writes the display of `chip8`, with each pixel enlarged to `scale` by `scale` pixels in PNG and PBM.
+/// Only PNG has colours, taken from `palette`.
+pub fn write_framebuffer<W: Write>(
+    mut writer: W,
+    chip8: &Chip8,
+    format: ImageFormat,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let width = chip8.screen_width();
+    let height = chip8.screen_height();
+    let pixels = chip8.framebuffer();
+    let scale = scale.max(1);
+    let scaled_rows = (0..height * scale).map(|y| {
+        let row = &pixels[(y / scale) * width..(y / scale + 1) * width];
+        row.iter().flat_map(move |&pixel| std::iter::repeat_n(pixel & 0b11, scale))
+    });
+    match format {
+        ImageFormat::Png => {
+            let rows: Vec<Vec<u8>> = scaled_rows.map(|row| row.collect()).collect();
+            write_png(writer, width * scale, height * scale, &rows, palette)
+        },
+        ImageFormat::Pbm => {
+            writeln!(writer, "P1")?;
+            writeln!(writer, "{} {}", width * scale, height * scale)?;
+            for row in scaled_rows {
+                let line: Vec<&str> = row.map(|pixel| if pixel != 0 { "1" } else { "0" }).collect();
+                writeln!(writer, "{}", line.join(" "))?;
+            }
+            Ok(())
+        },
+        ImageFormat::Ascii => {
+            for row in pixels.chunks(width) {
+                let line: String = row.iter().map(|&pixel| ASCII_PIXELS[(pixel & 0b11) as usize]).collect();
+                writeln!(writer, "{}", line)?;
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Writes `rows` of palette indices as an uncompressed 8-bit indexed PNG.
+fn write_png<W: Write>(mut writer: W, width: usize, height: usize, rows: &[Vec<u8>], palette: &Palette) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 3, 0, 0, 0]); // 8 bits per index, palette, deflate, no filter, no interlace
+    write_chunk(&mut writer, b"IHDR", &header)?;
+    write_chunk(&mut writer, b"PLTE", &palette.concat())?;
+
+    // Each row starts with filter type 0 (none).
+    let raw: Vec<u8> = rows.iter().flat_map(|row| std::iter::once(0).chain(row.iter().copied())).collect();
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        // Stored deflate blocks: a final flag, then the length and its complement.
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_chunk(&mut writer, b"IDAT", &zlib)?;
+    write_chunk(&mut writer, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(kind.iter().chain(data)).to_be_bytes())
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// The registers, stack, timers and counters of `chip8` as a JSON object, with the error
+/// that stopped it if any.
+pub fn registers_json(chip8: &Chip8, error: Option<&Chip8Error>) -> String {
+    let v: Vec<String> = chip8.registers().iter().map(|value| value.to_string()).collect();
+    let stack: Vec<String> = chip8.stack().iter().map(|address| address.to_string()).collect();
+    let error = match error {
+        Some(error) => format!("\"{}\"", json_escape(&error.to_string())),
+        None => String::from("null"),
+    };
+    format!(
+        "{{\"pc\":{},\"i\":{},\"v\":[{}],\"stack\":[{}],\"delay_timer\":{},\"sound_timer\":{},\
+         \"cycles\":{},\"frames\":{},\"exited\":{},\"error\":{}}}",
+        chip8.program_counter(),
+        chip8.index(),
+        v.join(","),
+        stack.join(","),
+        chip8.delay_timer(),
+        chip8.sound_timer(),
+        chip8.cycle_count(),
+        chip8.frame_count(),
+        chip8.has_exited(),
+        error
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::with_seed;
+
+    /// A machine showing the font's `0` in the top left corner.
+    fn zero_on_screen() -> Chip8 {
+        let program = [
+            0x60, 0x00, // V0 := 0
+            0xF0, 0x29, // I := font digit V0
+            0xD0, 0x05, // sprite V0 V0 5
+            0x12, 0x06, // jump 0x206
+        ];
+        let mut chip8 = with_seed(&program, 0);
+        chip8.run_cycles(3).unwrap();
+        chip8
+    }
+
+    /// Splits a PNG into its chunks, checking the signature and every CRC.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut rest = &png[8..];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = &rest[8 + length..12 + length];
+            assert_eq!(crc, &crc32(rest[4..8 + length].iter()).to_be_bytes());
+            chunks.push((String::from_utf8(kind.to_vec()).unwrap(), data.to_vec()));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    /// Undoes the stored deflate blocks written by `write_png`, checking the zlib checksum.
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(&zlib[..2], &[0x78, 0x01]);
+        let mut rest = &zlib[2..];
+        let mut raw = Vec::new();
+        loop {
+            let last = rest[0] == 1;
+            let length = u16::from_le_bytes([rest[1], rest[2]]);
+            assert_eq!(!length, u16::from_le_bytes([rest[3], rest[4]]));
+            raw.extend_from_slice(&rest[5..5 + length as usize]);
+            rest = &rest[5 + length as usize..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(rest, &adler32(&raw).to_be_bytes());
+        raw
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789".iter()), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn png_decodes_to_the_display() {
+        let chip8 = zero_on_screen();
+        let mut png = Vec::new();
+        write_framebuffer(&mut png, &chip8, ImageFormat::Png, 2, &DEFAULT_PALETTE).unwrap();
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 128, 0, 0, 0, 64, 8, 3, 0, 0, 0]);
+        assert_eq!(chunks[1].1, DEFAULT_PALETTE.concat());
+
+        let raw = inflate_stored(&chunks[2].1);
+        let rows: Vec<&[u8]> = raw.chunks(1 + 128).collect();
+        assert_eq!(rows.len(), 64);
+        let font_zero = [0xF0u8, 0x90, 0x90, 0x90, 0xF0];
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row[0], 0, "row {} uses a filter", y);
+            for x in 0..128 {
+                let lit = y < 10 && x < 8 && font_zero[y / 2] & (0x80 >> (x / 2)) != 0;
+                assert_eq!(row[1 + x], lit as u8, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn large_png_spans_several_blocks() {
+        let chip8 = zero_on_screen();
+        let mut png = Vec::new();
+        write_framebuffer(&mut png, &chip8, ImageFormat::Png, 6, &DEFAULT_PALETTE).unwrap();
+        let chunks = chunks(&png);
+        let raw = inflate_stored(&chunks[2].1);
+        assert_eq!(raw.len(), (32 * 6) * (1 + 64 * 6));
+        assert!(raw.len() > 0xFFFF);
+        assert_eq!(raw[1..7], [1; 6]);
+    }
+}
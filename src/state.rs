@@ -0,0 +1,146 @@
+//! The binary save state format written by [`Chip8::save_state`](crate::chip8::Chip8::save_state).
+//!
+//! A state starts with the magic bytes `C8ST` and a big-endian format version,
+//! followed by the machine's fields in a fixed order. States are only loaded
+//! by the version that wrote them.
+
+use std::error::Error;
+use std::fmt;
+
+/// Identifies a save state.
+pub const MAGIC: &[u8; 4] = b"C8ST";
+/// Version of the format written by this build; bumped whenever the layout changes.
+pub const STATE_VERSION: u16 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the magic bytes.
+    NotAState,
+    /// The state was written by a different version of the format.
+    UnsupportedVersion { found: u16, expected: u16 },
+    /// The data ends before all fields were read.
+    Truncated,
+    /// A field holds a value the machine can't be in.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { found, expected } => {
+                write!(f, "save state version {} is not supported (expected {})", found, expected)
+            },
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Appends big-endian fields to a state.
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> StateWriter {
+        let mut writer = StateWriter { bytes: Vec::new() };
+        writer.bytes(MAGIC);
+        writer.u16(STATE_VERSION);
+        writer
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads the fields written by [`StateWriter`] back in the same order.
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the magic bytes and version, leaving the reader at the first field.
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(StateError::NotAState);
+        }
+        let mut reader = StateReader { bytes: &bytes[MAGIC.len()..] };
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion { found: version, expected: STATE_VERSION });
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn bytes(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < length {
+            return Err(StateError::Truncated);
+        }
+        let (field, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let mut value = [0; 2];
+        value.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_be_bytes(value))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let mut value = [0; 4];
+        value.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(value))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(value))
+    }
+
+    /// Fails unless every byte of the state was read.
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if self.bytes.is_empty() { Ok(()) } else { Err(StateError::Invalid("length")) }
+    }
+}
@@ -0,0 +1,349 @@
+//! Per-instruction execution tracing.
+//!
+//! A [`Tracer`] attached with [`crate::Chip8::set_tracer`] receives a [`TraceEvent`] for
+//! every executed instruction that passes its [`TraceFilter`], and writes it as text or
+//! JSON lines. Register state is only captured while the tracer is enabled, so a
+//! disabled tracer costs next to nothing.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One human readable line per instruction.
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl TraceFormat {
+    /// Parses a format name as used on the command line: `text` or `json`.
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" | "jsonl" => Some(TraceFormat::JsonLines),
+            _ => None
+        }
+    }
+}
+
+/// Selects which instructions are traced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Bit N set traces opcodes whose high nibble is N, e.g. `1 << 0xD` for `DXYN`.
+    pub opcode_classes: u16,
+    /// Only trace instructions at addresses in this inclusive range.
+    pub address_range: Option<(u16, u16)>,
+}
+
+impl TraceFilter {
+    /// Traces every instruction.
+    pub fn all() -> TraceFilter {
+        TraceFilter {
+            opcode_classes: 0xFFFF,
+            address_range: None,
+        }
+    }
+
+    /// Parses a comma separated list of opcode classes, e.g. `"D,F"` or `"0,2"`.
+    pub fn parse_opcode_classes(classes: &str) -> Option<u16> {
+        classes.split(',').try_fold(0u16, |mask, class| {
+            u8::from_str_radix(class.trim(), 16)
+                .ok()
+                .filter(|&nibble| nibble < 16)
+                .map(|nibble| mask | 1 << nibble)
+        })
+    }
+
+    /// Parses an inclusive hexadecimal address range such as `"200-2FF"`.
+    pub fn parse_address_range(range: &str) -> Option<(u16, u16)> {
+        let mut bounds = range.splitn(2, '-');
+        let parse = |bound: &str| u16::from_str_radix(bound.trim().trim_start_matches("0x"), 16).ok();
+        let start = parse(bounds.next()?)?;
+        let end = parse(bounds.next()?)?;
+        if start <= end { Some((start, end)) } else { None }
+    }
+
+    pub fn matches(&self, address: u16, op_code: u16) -> bool {
+        let class_matches = self.opcode_classes & (1 << (op_code >> 12)) != 0;
+        let address_matches = match self.address_range {
+            Some((start, end)) => address >= start && address <= end,
+            None => true,
+        };
+        class_matches && address_matches
+    }
+}
+
+impl Default for TraceFilter {
+    fn default() -> TraceFilter {
+        TraceFilter::all()
+    }
+}
+
+/// The programmer visible CPU state around a traced instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub v: [u8; 16],
+    pub index: u16,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone)]
+pub struct TraceEvent<'a> {
+    /// Number of instructions executed before this one.
+    pub cycle: u64,
+    pub address: u16,
+    pub op_code: u16,
+    pub disassembly: &'a str,
+    pub before: &'a CpuState,
+    pub after: &'a CpuState,
+}
+
+/// Writes trace events to a file or any other writer.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    enabled: bool,
+}
+
+impl Tracer {
+    /// Creates an enabled tracer that traces every instruction.
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            writer,
+            format,
+            filter: TraceFilter::all(),
+            enabled: true,
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Pauses or resumes tracing without closing the output.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            let _ = self.writer.flush();
+        }
+    }
+
+    /// Returns whether an instruction at `address` would be traced.
+    pub fn wants(&self, address: u16, op_code: u16) -> bool {
+        self.enabled && self.filter.matches(address, op_code)
+    }
+
+    pub fn record(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let line = match self.format {
+            TraceFormat::Text => format!(
+                "{:>10} {:#06x}: {:04X}  {:<48} | {} -> {}",
+                event.cycle,
+                event.address,
+                event.op_code,
+                event.disassembly,
+                text_state(event.before),
+                text_state(event.after)
+            ),
+            TraceFormat::JsonLines => format!(
+                "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"disassembly\":\"{}\",\"before\":{},\"after\":{}}}",
+                event.cycle,
+                event.address,
+                event.op_code,
+                json_escape(event.disassembly),
+                json_state(event.before),
+                json_state(event.after)
+            ),
+        };
+        writeln!(self.writer, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn text_state(state: &CpuState) -> String {
+    let mut text = String::new();
+    for (i, value) in state.v.iter().enumerate() {
+        let _ = write!(text, "V{:X}={:02X} ", i, value);
+    }
+    let stack: Vec<String> = state.stack.iter().map(|address| format!("{:03X}", address)).collect();
+    let _ = write!(
+        text,
+        "I={:04X} S=[{}] DT={} ST={}",
+        state.index,
+        stack.join(","),
+        state.delay_timer,
+        state.sound_timer
+    );
+    text
+}
+
+fn json_state(state: &CpuState) -> String {
+    let v: Vec<String> = state.v.iter().map(|value| value.to_string()).collect();
+    let stack: Vec<String> = state.stack.iter().map(|address| address.to_string()).collect();
+    format!(
+        "{{\"v\":[{}],\"i\":{},\"stack\":[{}],\"delay_timer\":{},\"sound_timer\":{}}}",
+        v.join(","),
+        state.index,
+        stack.join(","),
+        state.delay_timer,
+        state.sound_timer
+    )
+}
+
+pub(crate) fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            },
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::chip8::with_seed;
+
+    /// A writer whose output stays readable after it was handed to a tracer.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(String::from).collect()
+        }
+    }
+
+    fn state(v0: u8, index: u16, stack: &[u16]) -> CpuState {
+        let mut v = [0; 16];
+        v[0] = v0;
+        CpuState { v, index, stack: stack.to_vec(), delay_timer: 3, sound_timer: 0 }
+    }
+
+    fn record(format: TraceFormat, disassembly: &str) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), format);
+        let (before, after) = (state(0, 0x210, &[]), state(5, 0x210, &[0x202]));
+        let event = TraceEvent { cycle: 7, address: 0x200, op_code: 0x6005, disassembly, before: &before, after: &after };
+        tracer.record(&event).unwrap();
+        buffer.lines()
+    }
+
+    #[test]
+    fn parses_opcode_classes() {
+        assert_eq!(TraceFilter::parse_opcode_classes("D,F"), Some(1 << 0xD | 1 << 0xF));
+        assert_eq!(TraceFilter::parse_opcode_classes(" 0 , 2 "), Some(0b101));
+        assert_eq!(TraceFilter::parse_opcode_classes("d"), Some(1 << 0xD));
+        assert_eq!(TraceFilter::parse_opcode_classes("10"), None);
+        assert_eq!(TraceFilter::parse_opcode_classes("D,G"), None);
+        assert_eq!(TraceFilter::parse_opcode_classes(""), None);
+    }
+
+    #[test]
+    fn parses_address_ranges() {
+        assert_eq!(TraceFilter::parse_address_range("200-2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(TraceFilter::parse_address_range("0x200 - 0x200"), Some((0x200, 0x200)));
+        assert_eq!(TraceFilter::parse_address_range("2FF-200"), None);
+        assert_eq!(TraceFilter::parse_address_range("200"), None);
+        assert_eq!(TraceFilter::parse_address_range("200-XYZ"), None);
+    }
+
+    #[test]
+    fn matches_classes_and_addresses() {
+        let filter = TraceFilter { opcode_classes: 1 << 0xD | 1 << 0xF, address_range: Some((0x200, 0x2FF)) };
+        assert!(filter.matches(0x200, 0xD015));
+        assert!(filter.matches(0x2FF, 0xF00A));
+        assert!(!filter.matches(0x300, 0xD015));
+        assert!(!filter.matches(0x1FE, 0xD015));
+        assert!(!filter.matches(0x200, 0x6005));
+        assert!(TraceFilter::all().matches(0xFFFF, 0x0000));
+    }
+
+    #[test]
+    fn writes_text_lines() {
+        let zeros = "V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00";
+        assert_eq!(
+            record(TraceFormat::Text, "v0 := 0x05"),
+            [format!(
+                "         7 0x0200: 6005  {:<48} | V0=00 {} I=0210 S=[] DT=3 ST=0 -> V0=05 {} I=0210 S=[202] DT=3 ST=0",
+                "v0 := 0x05", zeros, zeros
+            )]
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let zeros = "0,0,0,0,0,0,0,0,0,0,0,0,0,0,0";
+        assert_eq!(
+            record(TraceFormat::JsonLines, "say \"hi\""),
+            [format!(
+                "{{\"cycle\":7,\"pc\":512,\"opcode\":24581,\"disassembly\":\"say \\\"hi\\\"\",\
+                 \"before\":{{\"v\":[0,{z}],\"i\":528,\"stack\":[],\"delay_timer\":3,\"sound_timer\":0}},\
+                 \"after\":{{\"v\":[5,{z}],\"i\":528,\"stack\":[514],\"delay_timer\":3,\"sound_timer\":0}}}}",
+                z = zeros
+            )]
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(json_escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(json_escape("line\n\ttab\u{1}"), "line\\u000a\\u0009tab\\u0001");
+        assert_eq!(json_escape("é"), "é");
+    }
+
+    #[test]
+    fn traces_only_filtered_instructions() {
+        let program = [
+            0x60, 0x05, // V0 := 5
+            0xA2, 0x10, // I := 0x210
+            0xD0, 0x05, // sprite V0 V0 5
+            0x12, 0x06, // jump 0x206
+        ];
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), TraceFormat::Text);
+        tracer.set_filter(TraceFilter { opcode_classes: 1 << 0xD | 1 << 0x6, address_range: Some((0x202, 0x2FF)) });
+        let mut chip8 = with_seed(&program, 0);
+        chip8.set_tracer(Some(tracer));
+        chip8.run_cycles(6).unwrap();
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("         2 0x0204: D005"), "{}", lines[0]);
+
+        chip8.tracer_mut().unwrap().set_enabled(false);
+        chip8.run_cycles(6).unwrap();
+        assert_eq!(buffer.lines().len(), 1);
+    }
+}
@@ -0,0 +1,563 @@
+//! The windowed frontend behind the `run` command: the window, keyboard, gamepad and sound, and the
+//! `--debug` prompt. Only built with the `frontend` feature.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use chip8_rust::audio::{ToneGenerator, DEFAULT_SAMPLE_RATE};
+use chip8_rust::chip8;
+use chip8_rust::config::{Config, Table};
+use chip8_rust::debugger::{self, Debugger, StopReason};
+use chip8_rust::keymap::{KeyMap, Keypad};
+use chip8_rust::movie::{Movie, Playback};
+use chip8_rust::rewind::Rewind;
+use chip8_rust::snapshot::{Palette, DEFAULT_PALETTE};
+use chip8_rust::trace::{TraceFilter, TraceFormat, Tracer};
+use chip8_rust::TIMER_FREQUENCY;
+
+use crate::audio_output::AudioOutput;
+use crate::cli::{self, RunOptions};
+use crate::gamepad_input::{GamepadEvent, Gamepads};
+use crate::overlay;
+use crate::{create_chip8, read_movie, read_rom, rom_settings};
+
+use glutin_window::GlutinWindow as Window;
+use opengl_graphics::{GlGraphics, OpenGL};
+use piston::event_loop::{EventSettings, Events};
+use piston::input::{RenderArgs, RenderEvent, UpdateArgs, UpdateEvent, Key};
+use piston::window::{AdvancedWindow, Window as _, WindowSettings};
+use piston::input::*;
+
+struct Game {
+    gl: GlGraphics,
+    chip8: chip8::Chip8,
+    audio: Option<AudioOutput>,
+    palette: Palette,
+    debugger: Debugger,
+    /// Lines typed at the `--debug` prompt, read on a separate thread so the window keeps rendering.
+    commands: Option<Receiver<String>>,
+    overlay: bool,
+    redraw: bool,
+    quit: bool,
+    /// Save state slots are stored next to the ROM as `ROM.stateN`.
+    rom_path: String,
+    shift: bool,
+    keypad: Keypad,
+    rewind: Rewind,
+    rewinding: bool,
+    /// The `--record` movie and the file it is written to on exit.
+    recording: Option<(String, Movie)>,
+    playback: Option<Playback>,
+}
+
+fn read_commands() -> Receiver<String> {
+    let (sender, commands) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    commands
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+impl Game {
+    fn render(&mut self, args: &RenderArgs) {
+        use graphics::*;
+
+        if self.chip8.take_draw_flag() || self.redraw {
+            self.redraw = false;
+            let width = self.chip8.screen_width();
+            let height = self.chip8.screen_height();
+            let panel_width = if self.overlay { overlay::PANEL_WIDTH } else { 0.0 };
+            let game_width = args.window_size[0] - panel_width;
+            let pixel_size = (game_width / width as f64).min(args.window_size[1] / height as f64);
+            let gfx = self.chip8.framebuffer();
+            let running = self.debugger.is_running();
+            let panel = if self.overlay { Some(overlay::panel_lines(&self.chip8, running)) } else { None };
+            let palette = self.palette.map(|[r, g, b]| [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]);
+            self.gl.draw(args.viewport(), |c, gl| {
+              clear(palette[0], gl);
+              for y in 0..height {
+                  for x in 0..width {
+                      let pixel = gfx[(y * width) + x] as usize;
+                      if pixel != 0 {
+                          let rect = rectangle::square(0.0, 0.0, pixel_size);
+                          let transform = c
+                            .transform
+                            .trans(x as f64 * pixel_size, y as f64 * pixel_size);
+                          rectangle(palette[pixel & 0b11], rect, transform, gl);
+                      }
+                  }
+              }
+              if let Some(lines) = panel {
+                  let transform = c.transform.trans(game_width, 0.0);
+                  overlay::draw_panel(&lines, args.window_size[1], transform, gl);
+              }
+            });
+        }
+    }
+    fn update(&mut self, _args: &UpdateArgs) {
+        if self.commands.is_some() {
+            self.run_commands();
+        }
+        if self.rewinding {
+            // One recorded frame per update, so rewinding runs at the speed the game was played.
+            if self.rewind.step_back(&mut self.chip8) {
+                self.redraw = true;
+                if let Some((_, movie)) = &mut self.recording {
+                    movie.truncate(self.chip8.frame_count());
+                }
+            }
+        } else if self.debugger.is_running() {
+            if let Some(playback) = &mut self.playback {
+                playback.apply(&mut self.chip8);
+                if playback.is_finished(&self.chip8) {
+                    println!("Replay finished");
+                    self.playback = None;
+                }
+            }
+            if let Some(reason) = self.debugger.run_frame(&mut self.chip8) {
+                self.report_stop(reason);
+            }
+            self.rewind.record(&self.chip8);
+            self.redraw |= self.overlay;
+        }
+        if let Some(audio) = &self.audio {
+            let active = !self.rewinding && self.debugger.is_running() && self.chip8.sound_active();
+            audio.set_state(active, self.chip8.audio_pattern());
+        }
+    }
+
+    /// Runs the commands typed at the `--debug` prompt since the last update.
+    fn run_commands(&mut self) {
+        let commands = match &self.commands {
+            Some(commands) => commands,
+            None => return,
+        };
+        loop {
+            match commands.try_recv() {
+                Ok(line) => {
+                    match self.debugger.run_command(&mut self.chip8, &line) {
+                        Ok(output) if output.is_empty() => {},
+                        Ok(output) => println!("{}", output),
+                        Err(e) => println!("{}", e),
+                    }
+                    self.redraw = true;
+                    if self.debugger.quit_requested() {
+                        self.quit = true;
+                        return;
+                    }
+                    if !self.debugger.is_running() {
+                        prompt();
+                    }
+                },
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.quit = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn report_stop(&self, reason: StopReason) {
+        if self.commands.is_some() {
+            println!("{}\n{}", reason, debugger::format_state(&self.chip8));
+            prompt();
+        } else if let StopReason::Error(_) = reason {
+            eprintln!("{}", reason);
+        } else {
+            println!("{}", reason);
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.recording.is_some() {
+            println!("Pausing is disabled while recording a movie");
+            return;
+        }
+        if self.debugger.is_running() {
+            self.debugger.pause();
+            println!("Paused");
+            if self.commands.is_some() {
+                println!("{}", debugger::format_state(&self.chip8));
+                prompt();
+            }
+        } else {
+            self.debugger.resume();
+            println!("Resumed");
+        }
+        self.redraw = true;
+    }
+
+    fn step(&mut self) {
+        if self.debugger.is_running() {
+            return;
+        }
+        if self.recording.is_some() || self.playback.is_some() {
+            // A single instruction breaks the whole frames movies are made of.
+            println!("Stepping is disabled while recording or replaying a movie");
+            return;
+        }
+        match self.debugger.step(&mut self.chip8) {
+            Some(reason) => self.report_stop(reason),
+            None if self.commands.is_some() => {
+                println!("{}", debugger::format_state(&self.chip8));
+                prompt();
+            },
+            None => {},
+        }
+        self.redraw = true;
+    }
+
+    fn key_pressed(&mut self, key: Key) {
+        match key {
+            Key::Equals | Key::NumPadPlus => self.change_speed(1),
+            Key::Minus | Key::NumPadMinus => self.change_speed(-1),
+            Key::T => self.toggle_trace(),
+            Key::Backquote => {
+                self.overlay = !self.overlay;
+                self.redraw = true;
+            },
+            Key::P => self.toggle_pause(),
+            Key::N => self.step(),
+            Key::LShift | Key::RShift => self.shift = true,
+            Key::Backspace if self.playback.is_some() => println!("Rewinding is disabled while replaying a movie"),
+            Key::Backspace => self.rewinding = true,
+            _ => {}
+        }
+        if let Some(slot) = Game::state_slot(key) {
+            if self.shift {
+                self.save_state(slot);
+            } else if self.playback.is_some() {
+                println!("Loading states is disabled while replaying a movie");
+            } else if self.recording.is_some() {
+                // The movie couldn't reproduce a state from another session.
+                println!("Loading states is disabled while recording a movie");
+            } else {
+                self.load_state(slot);
+            }
+        }
+        self.input_changed(Button::Keyboard(key), true);
+    }
+
+    fn key_released(&mut self, key: Key) {
+        match key {
+            Key::LShift | Key::RShift => self.shift = false,
+            Key::Backspace => self.rewinding = false,
+            _ => {}
+        }
+        self.input_changed(Button::Keyboard(key), false);
+    }
+
+    /// Presses or releases the CHIP-8 keys bound to a keyboard key, mouse button or controller button or hat.
+    fn input_changed(&mut self, button: Button, pressed: bool) {
+        let changes = self.keypad.button(button, pressed);
+        self.set_keys(changes);
+    }
+
+    /// Presses or releases the CHIP-8 keys bound to the ends of a controller axis.
+    fn axis_moved(&mut self, args: &ControllerAxisArgs) {
+        let changes = self.keypad.axis(args);
+        self.set_keys(changes);
+    }
+
+    /// Passes on a game controller event read outside of the window.
+    fn gamepad_event(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Button(button, pressed) => self.input_changed(button, pressed),
+            GamepadEvent::Axis(args) => self.axis_moved(&args),
+            GamepadEvent::Disconnected(id) => {
+                let changes = self.keypad.controller_disconnected(id);
+                self.set_keys(changes);
+            },
+        }
+    }
+
+    /// Releases every CHIP-8 key when the window loses focus, as the release events go elsewhere.
+    fn focus_lost(&mut self) {
+        let changes = self.keypad.release_all();
+        self.set_keys(changes);
+    }
+
+    fn set_keys(&mut self, changes: Vec<(u8, bool)>) {
+        for (index, pressed) in changes {
+            self.set_key(index, pressed);
+        }
+    }
+
+    /// Passes a key change on to the machine, and to the movie when recording. While a movie
+    /// plays, the keyboard is ignored.
+    fn set_key(&mut self, index: u8, pressed: bool) {
+        if self.playback.is_some() || self.chip8.is_key_pressed(index) == pressed {
+            return;
+        }
+        self.chip8.set_key(index, pressed);
+        if let Some((_, movie)) = &mut self.recording {
+            movie.record(&self.chip8, index, pressed);
+        }
+        self.redraw |= self.overlay;
+    }
+
+    fn state_slot(key: Key) -> Option<u8> {
+        match key {
+            Key::F1 => Some(1),
+            Key::F2 => Some(2),
+            Key::F3 => Some(3),
+            Key::F4 => Some(4),
+            Key::F5 => Some(5),
+            Key::F6 => Some(6),
+            Key::F7 => Some(7),
+            Key::F8 => Some(8),
+            _ => None
+        }
+    }
+
+    fn state_path(&self, slot: u8) -> String {
+        format!("{}.state{}", self.rom_path, slot)
+    }
+
+    fn save_state(&mut self, slot: u8) {
+        let path = self.state_path(slot);
+        match fs::write(&path, self.chip8.save_state()) {
+            Ok(()) => println!("Saved state to slot {}", slot),
+            Err(e) => println!("Failed to write save state \"{}\" due to: {}", path, e),
+        }
+    }
+
+    fn load_state(&mut self, slot: u8) {
+        let path = self.state_path(slot);
+        let state = match fs::read(&path) {
+            Ok(state) => state,
+            Err(e) => {
+                println!("Failed to read save state \"{}\" due to: {}", path, e);
+                return;
+            }
+        };
+        if let Err(e) = self.chip8.load_state(&state) {
+            println!("Failed to load save state \"{}\": {}", path, e);
+            return;
+        }
+        println!("Loaded state from slot {}", slot);
+        self.redraw = true;
+        if self.commands.is_none() {
+            // A program that crashed or exited gets going again from the restored state.
+            self.debugger.resume();
+        } else if !self.debugger.is_running() {
+            println!("{}", debugger::format_state(&self.chip8));
+            prompt();
+        }
+    }
+
+    fn change_speed(&mut self, direction: i64) {
+        if self.recording.is_some() || self.playback.is_some() {
+            println!("The speed is fixed while recording or replaying a movie");
+            return;
+        }
+        let current = i64::from(self.chip8.cycles_per_frame());
+        let step = (current / 10).max(1);
+        let cycles_per_frame = (current + direction * step).max(1) as u32;
+        self.chip8.set_cycles_per_frame(cycles_per_frame);
+        println!("Speed: {} instructions per second", cycles_per_frame * TIMER_FREQUENCY);
+    }
+
+    fn toggle_trace(&mut self) {
+        if self.chip8.tracer().is_none() {
+            match open_tracer(DEFAULT_TRACE_PATH, TraceFormat::Text, TraceFilter::all()) {
+                Ok(tracer) => self.chip8.set_tracer(Some(tracer)),
+                Err(e) => {
+                    println!("Failed to open trace file \"{}\" due to: {}", DEFAULT_TRACE_PATH, e);
+                    return;
+                }
+            }
+            println!("Tracing to {}", DEFAULT_TRACE_PATH);
+        } else if let Some(tracer) = self.chip8.tracer_mut() {
+            let enabled = !tracer.is_enabled();
+            tracer.set_enabled(enabled);
+            println!("Tracing {}", if enabled { "resumed" } else { "paused" });
+        }
+    }
+}
+
+const DEFAULT_TRACE_PATH: &str = "chip8-trace.log";
+
+fn open_tracer(path: &str, format: TraceFormat, filter: TraceFilter) -> std::io::Result<Tracer> {
+    let file = File::create(path)?;
+    let mut tracer = Tracer::new(Box::new(BufWriter::new(file)), format);
+    tracer.set_filter(filter);
+    Ok(tracer)
+}
+
+/// Reads the key mappings from the `--config` file, or from the default configuration file if there is one,
+/// with the ROM settings database's keys for the ROM in between the general and the per-ROM mappings.
+fn load_key_map(config_path: Option<&str>, rom_path: &str, database_keys: Option<&Table>) -> Result<KeyMap, String> {
+    let path = match config_path {
+        Some(path) => PathBuf::from(path),
+        None => match cli::default_config_path() {
+            Some(path) if path.exists() => path,
+            _ => return KeyMap::from_config(&Config::default(), "", database_keys),
+        },
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read config \"{}\" due to: {}", path.display(), e))?;
+    let config = Config::parse(&text)
+        .map_err(|e| format!("Failed to read config \"{}\" due to: {}", path.display(), e))?;
+    let rom_name = Path::new(rom_path).file_name().map_or_else(|| rom_path.into(), |name| name.to_string_lossy());
+    KeyMap::from_config(&config, &rom_name, database_keys)
+        .map_err(|e| format!("Invalid key mapping in \"{}\": {}", path.display(), e))
+}
+
+/// Plays a ROM in a window until it is closed.
+pub fn run_command(options: RunOptions) -> Result<(), String> {
+    let rom_path = &options.rom_path;
+    let rom_bytes = read_rom(rom_path)?;
+    let settings = rom_settings(&options.machine, &rom_bytes)?;
+    let key_map = load_key_map(options.config_path.as_deref(), rom_path, settings.keys.as_ref())?;
+    let mut chip8 = create_chip8(&options.machine, &settings, rom_path, &rom_bytes)?;
+    let mut playback = None;
+    if let Some(replay_path) = &options.replay_path {
+        let movie = read_movie(replay_path)?;
+        // The movie's configuration replaces the command line's, or the replay would diverge.
+        chip8 = movie.start(&rom_bytes).map_err(|e| format!("Failed to replay \"{}\": {}", replay_path, e))?;
+        println!("Replaying {} frames from {}; the keyboard is ignored until it ends", movie.length, replay_path);
+        playback = Some(Playback::new(movie));
+    }
+    let recording = options.record_path.as_ref().map(|path| (path.clone(), Movie::new(&chip8, &rom_bytes)));
+    if let Some(trace_path) = &options.trace_path {
+        let tracer = open_tracer(trace_path, options.trace_format, options.trace_filter)
+            .map_err(|e| format!("Failed to open trace file \"{}\" due to: {}", trace_path, e))?;
+        chip8.set_tracer(Some(tracer));
+    }
+
+    let audio = match AudioOutput::new(ToneGenerator::new(options.tone, DEFAULT_SAMPLE_RATE)) {
+        Ok(audio) => Some(audio),
+        Err(e) => {
+            println!("Sound disabled: {}", e);
+            None
+        }
+    };
+
+    let mut gamepads = match Gamepads::new() {
+        Ok(gamepads) => Some(gamepads),
+        Err(e) => {
+            println!("Game controllers disabled: {}", e);
+            None
+        }
+    };
+
+    let mut debugger = Debugger::new();
+    let commands = if options.debug {
+        println!("{}", debugger::format_state(&chip8));
+        prompt();
+        Some(read_commands())
+    } else {
+        debugger.resume();
+        None
+    };
+
+    let opengl = OpenGL::V3_2;
+    let scale = f64::from(options.scale);
+    let window_size = [chip8::SCREEN_WIDTH as f64 * scale, chip8::SCREEN_HEIGHT as f64 * scale];
+    let rom_name = Path::new(rom_path).file_name().map_or_else(|| rom_path.into(), |name| name.to_string_lossy());
+    let title = format!("{} - chip8-rust", settings.title.as_deref().unwrap_or(&rom_name));
+    let mut window: Window = WindowSettings::new(title, window_size)
+        .graphics_api(opengl)
+        .fullscreen(options.fullscreen)
+        .exit_on_esc(true)
+        .build()
+        .map_err(|e| format!("Failed to open a window: {}", e))?;
+
+    let mut game = Game{
+        gl: GlGraphics::new(opengl),
+        chip8,
+        audio,
+        palette: options.palette.or(settings.palette).unwrap_or(DEFAULT_PALETTE),
+        debugger,
+        commands,
+        overlay: false,
+        redraw: false,
+        quit: false,
+        rom_path: options.rom_path.clone(),
+        shift: false,
+        keypad: Keypad::new(key_map),
+        rewind: Rewind::new(options.rewind_seconds.saturating_mul(TIMER_FREQUENCY) as usize),
+        rewinding: false,
+        recording,
+        playback,
+    };
+
+    let event_settings = EventSettings{
+        max_fps: 500,
+        ups: u64::from(TIMER_FREQUENCY),
+        ups_reset: 5,
+        swap_buffers: true,
+        bench_mode: false,
+        lazy: false,
+    };
+    let mut events = Events::new(event_settings);
+    while let Some(e) = events.next(&mut window) {
+        if let Some(args) = e.render_args() {
+            game.render(&args);
+        }
+
+        if let Some(args) = e.update_args() {
+            for event in gamepads.iter_mut().flat_map(Gamepads::poll) {
+                game.gamepad_event(event);
+            }
+            game.update(&args);
+            if game.quit {
+                window.set_should_close(true);
+            }
+        }
+
+        match e.press_args() {
+            Some(Button::Keyboard(key)) => {
+                let overlay = game.overlay;
+                game.key_pressed(key);
+                if game.overlay != overlay {
+                    // Make room for the panel beside the game instead of shrinking it.
+                    let size = window.size();
+                    let panel_width = if game.overlay { overlay::PANEL_WIDTH } else { -overlay::PANEL_WIDTH };
+                    window.set_size([size.width + panel_width, size.height]);
+                }
+            },
+            Some(button) => game.input_changed(button, true),
+            None => {}
+        }
+
+        if e.resize_args().is_some() {
+            game.redraw = true;
+        }
+
+        match e.release_args() {
+            Some(Button::Keyboard(key)) => game.key_released(key),
+            Some(button) => game.input_changed(button, false),
+            None => {}
+        }
+
+        if let Some(args) = e.controller_axis_args() {
+            game.axis_moved(&args);
+        }
+
+        if e.focus_args() == Some(false) {
+            game.focus_lost();
+        }
+    }
+
+    if let Some((path, mut movie)) = game.recording.take() {
+        movie.finish(&game.chip8);
+        fs::write(&path, movie.to_text()).map_err(|e| format!("Failed to write movie \"{}\" due to: {}", path, e))?;
+        println!("Recorded {} frames to {}", movie.length, path);
+    }
+    Ok(())
+}